@@ -0,0 +1,68 @@
+use log::info;
+use serde_json::json;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use crate::{sign_in, ApiSession, Credentials};
+
+// 用于账号间转存的其他账号，以用户名为键保存
+const ACCOUNT_STORE: &str = "accounts.json";
+
+pub async fn add(app: &AppHandle, username: &str, password: &str) -> Result<(), String> {
+    // 和 open 一样使用单独的客户端，不带上当前账号的 Cookie
+    let token = sign_in(&ApiSession::new(), username, password).await?;
+    let store = app.store(ACCOUNT_STORE).map_err(|e| e.to_string())?;
+    store.set(
+        username,
+        json!(Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+            token: Some(token),
+        }),
+    );
+    store.save().map_err(|e| e.to_string())?;
+    info!("已保存账号: {}", username);
+    Ok(())
+}
+
+pub fn list(app: &AppHandle) -> Vec<String> {
+    let Ok(store) = app.store(ACCOUNT_STORE) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = store.entries().into_iter().map(|(k, _)| k).collect();
+    names.sort();
+    names
+}
+
+pub fn remove(app: &AppHandle, username: &str) -> Result<(), String> {
+    let store = app.store(ACCOUNT_STORE).map_err(|e| e.to_string())?;
+    store.delete(username);
+    store.save().map_err(|e| e.to_string())
+}
+
+// 重新登录保存的账号，返回该账号的会话
+// 保存的 Token 可能已经过期，转存耗时较长，每次都用密码登录
+pub async fn open(app: &AppHandle, username: &str) -> Result<ApiSession, String> {
+    let store = app.store(ACCOUNT_STORE).map_err(|e| e.to_string())?;
+    let value = store
+        .get(username)
+        .ok_or_else(|| format!("没有保存账号: {}", username))?;
+    let creds: Credentials =
+        serde_json::from_value(value).map_err(|_| "凭证格式错误".to_string())?;
+
+    // 使用单独的客户端，避免和当前账号共用 Cookie
+    let api = ApiSession::new();
+    let token = sign_in(&api, &creds.username, &creds.password).await?;
+    *api.token.lock().unwrap() = token.clone();
+
+    store.set(
+        username,
+        json!(Credentials {
+            token: Some(token),
+            ..creds
+        }),
+    );
+    store.save().map_err(|e| e.to_string())?;
+    info!("已登录账号: {}", username);
+    Ok(api)
+}
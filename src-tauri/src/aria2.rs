@@ -0,0 +1,229 @@
+use log::info;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::download::{self, DownloadJob};
+use crate::models::FileInfo;
+use crate::AppState;
+
+// CDN 会检查 User-Agent，交给 aria2 下载时带上和客户端一样的请求头
+const USER_AGENT: &str = "123pan/v2.4.0(Android_7.1.2;Xiaomi)";
+
+// 一个可以交给 aria2 的下载链接
+#[derive(Clone, Serialize, Debug)]
+pub struct Aria2Link {
+    pub url: String,
+    pub out: String,
+    pub dir: Option<String>,
+    pub headers: Vec<String>,
+}
+
+impl Aria2Link {
+    // addUri 的 options 参数
+    fn options(&self) -> Value {
+        let mut options = json!({
+            "out": self.out,
+            "header": self.headers,
+        });
+        if let Some(dir) = &self.dir {
+            options["dir"] = json!(dir);
+        }
+        options
+    }
+}
+
+// 每个文件的推送结果，失败时带上原因
+#[derive(Clone, Serialize, Debug)]
+pub struct Aria2Result {
+    pub file_id: i64,
+    pub file_name: String,
+    pub gid: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+// 走和普通下载一样的流程解析出真实地址，文件夹按打包下载处理
+pub async fn resolve_link(
+    state: &AppState,
+    info: &FileInfo,
+    dir: Option<&str>,
+) -> Result<Aria2Link, String> {
+    let url =
+        download::resolve_url(&state.api, &state.resolver, &DownloadJob::for_file(info)).await?;
+    let out = if info.file_type == 1 {
+        format!("{}.zip", info.file_name)
+    } else {
+        info.file_name.clone()
+    };
+
+    Ok(Aria2Link {
+        url,
+        out,
+        dir: dir.map(str::to_string),
+        headers: vec![format!("User-Agent: {}", USER_AGENT)],
+    })
+}
+
+// 调用 aria2.addUri，返回任务的 GID
+pub async fn add_uri(
+    client: &Client,
+    rpc_url: &str,
+    secret: Option<&str>,
+    link: &Aria2Link,
+) -> Result<String, String> {
+    let mut params = Vec::new();
+    if let Some(secret) = secret.filter(|s| !s.is_empty()) {
+        params.push(json!(format!("token:{}", secret)));
+    }
+    params.push(json!([link.url]));
+    params.push(link.options());
+
+    let payload = json!({
+        "jsonrpc": "2.0",
+        "id": Uuid::new_v4().simple().to_string(),
+        "method": "aria2.addUri",
+        "params": params,
+    });
+
+    let res = client
+        .post(rpc_url)
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| format!("连接 aria2 失败: {}", e))?;
+    // aria2 出错时状态码不是 200，但响应体里仍有错误信息
+    let body: RpcResponse = res
+        .json()
+        .await
+        .map_err(|e| format!("aria2 响应格式错误: {}", e))?;
+
+    if let Some(err) = body.error {
+        return Err(format!("aria2 返回错误 {}: {}", err.code, err.message));
+    }
+    let gid = body
+        .result
+        .and_then(|v| v.as_str().map(str::to_string))
+        .ok_or("aria2 未返回 GID")?;
+    info!("已推送到 aria2: {} ({})", link.out, gid);
+    Ok(gid)
+}
+
+// 生成 aria2 的输入文件 (aria2c -i)，每个链接一行，选项缩进写在下面
+pub fn input_file(links: &[Aria2Link]) -> String {
+    let mut text = String::new();
+    for link in links {
+        text.push_str(&link.url);
+        text.push('\n');
+        text.push_str(&format!("  out={}\n", link.out));
+        if let Some(dir) = &link.dir {
+            text.push_str(&format!("  dir={}\n", dir));
+        }
+        for header in &link.headers {
+            text.push_str(&format!("  header={}\n", header));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, serve};
+
+    fn link(dir: Option<&str>) -> Aria2Link {
+        Aria2Link {
+            url: "https://cdn.example.com/a.zip?sign=1".to_string(),
+            out: "a.zip".to_string(),
+            dir: dir.map(str::to_string),
+            headers: vec![format!("User-Agent: {}", USER_AGENT)],
+        }
+    }
+
+    // 空行之后是 JSON 请求体
+    fn request_json(request: &str) -> Value {
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn options_include_dir_only_when_set() {
+        assert_eq!(
+            link(None).options(),
+            json!({ "out": "a.zip", "header": [format!("User-Agent: {}", USER_AGENT)] })
+        );
+        assert_eq!(link(Some("/data")).options()["dir"], json!("/data"));
+    }
+
+    #[test]
+    fn input_file_lists_options_under_each_link() {
+        let text = input_file(&[link(Some("/data")), link(None)]);
+        let expected = format!(
+            "https://cdn.example.com/a.zip?sign=1\n  out=a.zip\n  dir=/data\n  header=User-Agent: {ua}\n\
+             https://cdn.example.com/a.zip?sign=1\n  out=a.zip\n  header=User-Agent: {ua}\n",
+            ua = USER_AGENT
+        );
+        assert_eq!(text, expected);
+    }
+
+    #[tokio::test]
+    async fn add_uri_returns_gid() {
+        let ok = response(
+            "200 OK",
+            &[("Content-Type", "application/json")],
+            r#"{"jsonrpc":"2.0","id":"1","result":"2089b05ecca3d829"}"#,
+        );
+        let (base, server) = serve(vec![ok]).await;
+
+        let gid = add_uri(
+            &Client::new(),
+            &format!("{}/jsonrpc", base),
+            Some("s3cret"),
+            &link(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(gid, "2089b05ecca3d829");
+
+        let request = request_json(&server.await.unwrap()[0]);
+        assert_eq!(request["method"], "aria2.addUri");
+        assert_eq!(
+            request["params"],
+            json!(["token:s3cret", [link(None).url], link(None).options()])
+        );
+    }
+
+    #[tokio::test]
+    async fn add_uri_reports_rpc_error() {
+        let err = response(
+            "400 Bad Request",
+            &[("Content-Type", "application/json")],
+            r#"{"jsonrpc":"2.0","id":"1","error":{"code":1,"message":"Unauthorized"}}"#,
+        );
+        let (base, server) = serve(vec![err]).await;
+
+        let result = add_uri(
+            &Client::new(),
+            &format!("{}/jsonrpc", base),
+            None,
+            &link(None),
+        )
+        .await;
+        assert_eq!(result, Err("aria2 返回错误 1: Unauthorized".to_string()));
+
+        // 没有密钥时不带 token 参数
+        let request = request_json(&server.await.unwrap()[0]);
+        assert_eq!(request["params"].as_array().unwrap().len(), 2);
+    }
+}
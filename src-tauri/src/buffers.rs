@@ -0,0 +1,75 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+// 上传分块用的缓冲区池: 用完的缓冲区放回池中给下一块使用，不再为每块重新申请内存
+// 同时使用的缓冲区数由调用方的并发数决定，池中最多保留 max_idle 个空闲的
+pub struct BufferPool {
+    capacity: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize, max_idle: usize) -> Arc<Self> {
+        Arc::new(Self {
+            capacity,
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    // 取出一个空缓冲区，容量为一块的大小
+    pub fn take(self: &Arc<Self>) -> PooledBuffer {
+        let data = self
+            .idle
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity));
+        PooledBuffer {
+            data,
+            pool: self.clone(),
+        }
+    }
+}
+
+// 从池中取出的缓冲区，释放时自动放回
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut data = std::mem::take(&mut self.data);
+        // 被扩容过的缓冲区不放回，免得池里的内存越来越大
+        if data.capacity() != self.pool.capacity {
+            return;
+        }
+        data.clear();
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.max_idle {
+            idle.push(data);
+        }
+    }
+}
@@ -0,0 +1,861 @@
+use futures_util::future::join_all;
+use futures_util::StreamExt;
+use log::{debug, info, warn};
+use md5::{Digest, Md5};
+use reqwest::header::{ACCEPT_RANGES, CONTENT_RANGE, RANGE};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::models::{DownloadInfoResponse, FileInfo};
+use crate::progress::{ProgressTracker, Snapshot};
+use crate::resolver::DownloadUrlResolver;
+use crate::throttle::Throttle;
+use crate::{add_auth_headers, ApiSession, AppState, ProgressPayload};
+
+// 每写入这么多字节更新一次续传记录
+const SAVE_INTERVAL: u64 = 4 * 1024 * 1024;
+// 单个分段的最小长度，文件太小时不值得拆分
+const MIN_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+pub const MAX_CONNECTIONS: u32 = 16;
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+// 一个下载任务的描述
+#[derive(Clone)]
+pub struct DownloadJob {
+    pub id: String, // 任务 ID，同一文件下载多次也不会冲突
+    pub file_id: i64,
+    pub file_name: String,
+    pub file_type: i32,
+    pub etag: String,
+    pub s3_key_flag: String,
+    pub size: i64,
+    pub save_path: String,
+    pub connections: u32,
+    pub retry_on_mismatch: bool, // 校验失败时删除并重新下载一次
+    pub max_retries: u32,        // 地址过期或连接中断时最多重新获取地址的次数
+    pub group: Option<String>,   // 所属的文件夹任务
+}
+
+impl DownloadJob {
+    // 由网盘条目创建任务，保存路径为空，需要下载到本地时由调用方设置
+    pub fn for_file(info: &FileInfo) -> Self {
+        Self {
+            id: String::new(),
+            file_id: info.file_id,
+            file_name: info.file_name.clone(),
+            file_type: info.file_type,
+            etag: info.etag.clone().unwrap_or_default(),
+            s3_key_flag: info.s3_key_flag.clone().unwrap_or_else(|| "0".to_string()),
+            size: info.size,
+            save_path: String::new(),
+            connections: 1,
+            retry_on_mismatch: false,
+            max_retries: 0,
+            group: None,
+        }
+    }
+
+    // 打包下载的内容由服务器临时生成，无法续传
+    fn resumable(&self) -> bool {
+        self.file_type != 1
+    }
+
+    // 下载地址缓存的键
+    fn cache_key(&self) -> String {
+        format!("{}:{}", self.file_id, self.etag)
+    }
+
+    // 单文件的 Etag 就是内容的 MD5，可以用来校验
+    fn verifiable(&self) -> bool {
+        self.resumable()
+            && self.etag.len() == 32
+            && self.etag.chars().all(|c| c.is_ascii_hexdigit())
+    }
+}
+
+// 下载失败的原因: 地址过期或连接中断时可以重新获取地址后继续
+enum FetchError {
+    Retryable(String),
+    Fatal(String),
+}
+
+impl From<String> for FetchError {
+    fn from(e: String) -> Self {
+        FetchError::Fatal(e)
+    }
+}
+
+impl From<FetchError> for String {
+    fn from(e: FetchError) -> Self {
+        match e {
+            FetchError::Retryable(e) | FetchError::Fatal(e) => e,
+        }
+    }
+}
+
+// 签名过期时 CDN 返回 403 或 410
+fn check_status(status: StatusCode) -> Result<(), FetchError> {
+    if status == StatusCode::FORBIDDEN || status == StatusCode::GONE {
+        return Err(FetchError::Retryable(format!("下载地址已失效: {}", status)));
+    }
+    if !status.is_success() {
+        return Err(FetchError::Fatal(format!("服务器返回错误状态: {}", status)));
+    }
+    Ok(())
+}
+
+// 分段下载中的一段，end 为闭区间
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Segment {
+    pub start: u64,
+    pub end: u64,
+    pub done: u64,
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        self.end + 1 - self.start
+    }
+
+    fn is_complete(&self) -> bool {
+        self.done >= self.len()
+    }
+}
+
+// 断点续传的进度记录，与 .part 文件放在一起
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartState {
+    pub file_id: i64,
+    pub etag: String,
+    pub size: i64,
+    // 从文件开头起连续写好的字节数
+    pub downloaded: u64,
+    // 多线程下载时各分段的进度，单线程时为空
+    #[serde(default)]
+    pub segments: Vec<Segment>,
+}
+
+impl PartState {
+    pub fn new(file_id: i64, etag: &str, size: i64) -> Self {
+        Self {
+            file_id,
+            etag: etag.to_string(),
+            size,
+            downloaded: 0,
+            segments: Vec::new(),
+        }
+    }
+
+    // 只有同一个文件 (id、etag、大小都一致) 才允许续传
+    fn same_file(&self, other: &PartState) -> bool {
+        self.file_id == other.file_id && self.etag == other.etag && self.size == other.size
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("保存续传记录失败: {}", e))
+    }
+
+    // 根据分段进度计算连续的前缀长度，方便切换回单线程续传
+    fn update_prefix(&mut self) {
+        let mut prefix = 0;
+        for seg in &self.segments {
+            if seg.start != prefix {
+                break;
+            }
+            prefix += seg.done.min(seg.len());
+            if !seg.is_complete() {
+                break;
+            }
+        }
+        self.downloaded = prefix;
+    }
+}
+
+// 下载中的临时文件: xxx.part
+pub fn part_path(save_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.part", save_path))
+}
+
+// 续传记录: xxx.part.json
+pub fn state_path(save_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.part.json", save_path))
+}
+
+// 把网盘中的相对路径 (用 / 分隔) 接到本地目录下
+// 文件名来自服务器，每一段都必须是普通的名字，防止 ".."、绝对路径或盘符跳出 root
+pub fn local_path(root: &Path, relative: &str) -> Result<PathBuf, String> {
+    let mut path = root.to_path_buf();
+    for name in relative.split('/') {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(c)), None) if c == name => path.push(c),
+            _ => return Err(format!("不安全的文件路径: {}", relative)),
+        }
+    }
+    Ok(path)
+}
+
+pub fn emit_progress(app: &AppHandle, job: &DownloadJob, snapshot: Snapshot, status: &str) {
+    emit(app, job, snapshot, status, None);
+}
+
+// 任务失败，带上原因
+pub fn emit_error(app: &AppHandle, job: &DownloadJob, snapshot: Snapshot, error: &str) {
+    emit(app, job, snapshot, "error", Some(error.to_string()));
+}
+
+fn emit(
+    app: &AppHandle,
+    job: &DownloadJob,
+    snapshot: Snapshot,
+    status: &str,
+    error: Option<String>,
+) {
+    let state = app.state::<AppState>();
+    state.downloads.record(&job.id, snapshot);
+    if job.group.is_some() {
+        state.downloads.update_group(app, job, &snapshot, status);
+    }
+    app.emit(
+        "download-progress",
+        ProgressPayload {
+            id: job.id.clone(),
+            file_id: job.file_id,
+            status: status.to_string(),
+            snapshot,
+            error,
+        },
+    )
+    .unwrap_or(());
+}
+
+// 读取与当前文件匹配的续传记录，不匹配时返回 None
+fn load_state(part: &Path, state: &Path, expected: &PartState) -> Option<(PartState, u64)> {
+    let saved = PartState::load(state).filter(|s| s.same_file(expected))?;
+    let on_disk = fs::metadata(part).map(|m| m.len()).ok()?;
+    Some((saved, on_disk))
+}
+
+// 计算可以续传的起点，记录不匹配或文件缺失时返回 0
+fn resume_offset(part: &Path, state: &Path, expected: &PartState) -> u64 {
+    let Some((saved, on_disk)) = load_state(part, state, expected) else {
+        return 0;
+    };
+    // 以两者中较小的为准，避免记录落后于实际写入或文件被截断
+    let offset = saved.downloaded.min(on_disk);
+    if expected.size > 0 && offset >= expected.size as u64 {
+        return 0;
+    }
+    offset
+}
+
+// 打开 .part 文件: offset 为 0 时重新创建，否则截断到 offset 并定位到末尾
+fn open_part_file(part: &Path, offset: u64) -> Result<File, String> {
+    if offset == 0 {
+        return File::create(part).map_err(|e| format!("创建文件失败: {}", e));
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(part)
+        .map_err(|e| format!("打开临时文件失败: {}", e))?;
+    file.set_len(offset)
+        .map_err(|e| format!("截断临时文件失败: {}", e))?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| format!("定位临时文件失败: {}", e))?;
+    Ok(file)
+}
+
+// 解析 Content-Range: bytes 100-199/200 中的起始位置
+fn content_range_start(value: &str) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+// 解析 Content-Range: bytes 100-199/200 中的总长度
+fn content_range_total(value: &str) -> Option<u64> {
+    let (_, total) = value.trim().rsplit_once('/')?;
+    total.trim().parse().ok()
+}
+
+// 续传时先计算已下载部分的 MD5，后续数据边下边算
+async fn hash_prefix(part: &Path, len: u64) -> Result<Md5, String> {
+    let part = part.to_path_buf();
+    tauri::async_runtime::spawn_blocking(move || -> Result<Md5, String> {
+        let file = File::open(&part).map_err(|e| e.to_string())?;
+        let mut reader = file.take(len);
+        let mut hasher = Md5::new();
+        let mut buffer = [0; 8192];
+        loop {
+            let count = reader.read(&mut buffer).map_err(|e| e.to_string())?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buffer[..count]);
+        }
+        Ok(hasher)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// 下载完成: 将 .part 改名为目标文件并清理续传记录
+fn finish_part(part: &Path, state: &Path, save_path: &str) -> Result<(), String> {
+    fs::rename(part, save_path).map_err(|e| format!("重命名文件失败: {}", e))?;
+    let _ = fs::remove_file(state);
+    Ok(())
+}
+
+// 取消下载: 删除 .part 文件和续传记录
+pub fn discard_part(save_path: &str) {
+    let _ = fs::remove_file(part_path(save_path));
+    let _ = fs::remove_file(state_path(save_path));
+}
+
+// 把 [from, total) 平均拆成若干段
+fn plan_segments(from: u64, total: u64, connections: u32) -> Vec<Segment> {
+    let remaining = total - from;
+    let count = (remaining / MIN_SEGMENT_SIZE).clamp(1, connections.max(1) as u64);
+    let seg_len = remaining.div_ceil(count);
+
+    let mut segments = Vec::new();
+    let mut start = from;
+    while start < total {
+        let end = (start + seg_len).min(total) - 1;
+        segments.push(Segment {
+            start,
+            end,
+            done: 0,
+        });
+        start = end + 1;
+    }
+    segments
+}
+
+// 用 bytes=0-0 试探服务器是否支持 Range，支持时返回文件总长度
+async fn probe_ranges(client: &Client, url: &str) -> Option<u64> {
+    let res = client
+        .get(url)
+        .header(RANGE, "bytes=0-0")
+        .send()
+        .await
+        .ok()?;
+
+    let advertised = res
+        .headers()
+        .get(ACCEPT_RANGES)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("bytes"))
+        .unwrap_or(false);
+    if !advertised && res.status() != StatusCode::PARTIAL_CONTENT {
+        return None;
+    }
+
+    res.headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(content_range_total)
+}
+
+// 获取下载地址: 单文件走 download_info，文件夹走打包下载
+// 用 api 所属的账号请求，中间页统一交给 resolver 解析和缓存
+pub async fn resolve_url(
+    api: &ApiSession,
+    resolver: &DownloadUrlResolver,
+    job: &DownloadJob,
+) -> Result<String, String> {
+    // 打包下载的地址每次都不同，不缓存
+    let cache_key = job.resumable().then(|| job.cache_key());
+    if let Some(url) = cache_key.as_deref().and_then(|k| resolver.cached(k)) {
+        debug!("使用缓存的下载地址: {}", job.file_name);
+        return Ok(url);
+    }
+
+    let client = &api.client;
+    let token = api.token.lock().unwrap().clone();
+
+    // 步骤 1: 根据类型选择 API 和 Payload
+    let intermediate_url;
+
+    if job.file_type == 1 {
+        // --- 文件夹逻辑 ---
+        let batch_url = "https://www.123pan.com/a/api/file/batch_download_info";
+        let payload = json!({
+            "fileIdList": [{ "fileId": job.file_id }]
+        });
+
+        let req = client.post(batch_url).json(&payload);
+        let req = add_auth_headers(req, &token, &api.login_uuid);
+        let res = req.send().await.map_err(|e| e.to_string())?;
+        let info_res: DownloadInfoResponse = res.json().await.map_err(|e| e.to_string())?;
+
+        if info_res.code != 0 {
+            return Err(format!("获取打包下载链接失败: {}", info_res.message));
+        }
+        intermediate_url = info_res.data.map(|d| d.download_url).ok_or("链接为空")?;
+    } else {
+        // --- 单文件逻辑 ---
+        let info_url = "https://www.123pan.com/a/api/file/download_info";
+        let payload = json!({
+            "driveId": 0,
+            "fileId": job.file_id,
+            "etag": job.etag,
+            "s3keyFlag": job.s3_key_flag,
+            "type": 0,
+            "fileName": job.file_name,
+            "size": job.size
+        });
+
+        let req = client.post(info_url).json(&payload);
+        let req = add_auth_headers(req, &token, &api.login_uuid);
+        let res = req.send().await.map_err(|e| e.to_string())?;
+        let info_res: DownloadInfoResponse = res.json().await.map_err(|e| e.to_string())?;
+
+        if info_res.code != 0 {
+            return Err(format!("获取下载链接失败: {}", info_res.message));
+        }
+        intermediate_url = info_res.data.map(|d| d.download_url).ok_or("链接为空")?;
+    }
+
+    // 步骤 2: 解析中间页
+    resolver
+        .resolve(cache_key.as_deref(), &intermediate_url)
+        .await
+}
+
+// 执行一个下载任务: 获取地址后按 job.connections 选择单线程或分段下载
+pub async fn run(app: &AppHandle, job: &DownloadJob) -> Result<(), String> {
+    info!("开始下载: {} (Type: {})", job.file_name, job.file_type);
+
+    let state = app.state::<AppState>();
+    let mut url = resolve_url(&state.api, &state.resolver, job).await?;
+    let client = &state.api.client;
+    let throttle = state.limits.download.for_task(&job.id);
+
+    // 真实下载 (先写入 .part，支持断点续传和分段下载)
+    let mut tracker = ProgressTracker::new(job.size.max(0) as u64, 0);
+    let part_path = part_path(&job.save_path);
+    let state_path = state_path(&job.save_path);
+    let mut retried = false;
+    let mut reresolved = 0;
+
+    loop {
+        let streamed_md5 = match fetch_once(client, &url, job, &throttle, &mut tracker, app).await {
+            Ok(md5) => md5,
+            // 已写入的数据保存在续传记录中，换新地址后从当前位置继续
+            Err(FetchError::Retryable(e)) if reresolved < job.max_retries => {
+                reresolved += 1;
+                warn!(
+                    "{}，重新获取下载地址 ({}/{}): {}",
+                    e, reresolved, job.max_retries, job.file_name
+                );
+                emit_progress(app, job, tracker.snapshot(), "reconnecting");
+                state.resolver.invalidate(&job.cache_key());
+                url = resolve_url(&state.api, &state.resolver, job).await?;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if job.verifiable() {
+            // 分段下载无法按顺序计算 MD5，只能下载完再读一遍
+            let actual = match streamed_md5 {
+                Some(md5) => md5,
+                None => {
+                    emit_progress(app, job, tracker.snapshot(), "verifying");
+                    let path = part_path.to_string_lossy().to_string();
+                    crate::calculate_file_md5(path).await?.0
+                }
+            };
+
+            if !actual.eq_ignore_ascii_case(&job.etag) {
+                warn!(
+                    "文件校验失败: {} (期望 {}, 实际 {})",
+                    job.file_name, job.etag, actual
+                );
+                emit_progress(app, job, tracker.snapshot(), "verify-failed");
+                discard_part(&job.save_path);
+
+                if job.retry_on_mismatch && !retried {
+                    info!("删除损坏的文件并重新下载: {}", job.file_name);
+                    retried = true;
+                    tracker.reset(0);
+                    continue;
+                }
+                return Err(format!("文件校验失败: {}", job.file_name));
+            }
+            info!("文件校验通过: {}", job.file_name);
+        }
+        break;
+    }
+
+    finish_part(&part_path, &state_path, &job.save_path)?;
+    info!("文件下载完成: {}", job.file_name);
+    emit_progress(app, job, tracker.finished(), "finished");
+    Ok(())
+}
+
+// 下载一次到 .part 文件，能边下边算 MD5 时返回结果
+async fn fetch_once(
+    client: &Client,
+    url: &str,
+    job: &DownloadJob,
+    throttle: &Throttle,
+    tracker: &mut ProgressTracker,
+    app: &AppHandle,
+) -> Result<Option<String>, FetchError> {
+    if job.resumable() && job.connections > 1 {
+        match probe_ranges(client, url).await {
+            Some(total) if total >= 2 * MIN_SEGMENT_SIZE => {
+                fetch_segmented(client, url, job, total, throttle, tracker, app).await?;
+                return Ok(None);
+            }
+            Some(_) => {}
+            None => warn!("服务器不支持 Range，改用单线程下载: {}", job.file_name),
+        }
+    }
+    fetch_single(client, url, job, throttle, tracker, app).await
+}
+
+async fn fetch_single(
+    client: &Client,
+    url: &str,
+    job: &DownloadJob,
+    throttle: &Throttle,
+    tracker: &mut ProgressTracker,
+    app: &AppHandle,
+) -> Result<Option<String>, FetchError> {
+    let part_path = part_path(&job.save_path);
+    let state_path = state_path(&job.save_path);
+    let mut part_state = PartState::new(job.file_id, &job.etag, job.size);
+
+    let mut offset = if job.resumable() {
+        resume_offset(&part_path, &state_path, &part_state)
+    } else {
+        0
+    };
+
+    let mut req = client.get(url);
+    if offset > 0 {
+        info!("从 {} 字节处继续下载: {}", offset, job.file_name);
+        req = req.header(RANGE, format!("bytes={}-", offset));
+    }
+    let res = req
+        .send()
+        .await
+        .map_err(|e| FetchError::Retryable(format!("下载请求失败: {}", e)))?;
+    check_status(res.status())?;
+
+    if offset > 0 {
+        let range_start = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_range_start);
+
+        if res.status() != StatusCode::PARTIAL_CONTENT || range_start != Some(offset) {
+            warn!("服务器不支持断点续传，重新下载: {}", job.file_name);
+            offset = 0;
+            tracker.reset(0);
+            emit_progress(app, job, tracker.snapshot(), "restarted");
+        }
+    }
+
+    // 注意：文件夹打包下载时，API 返回的 Size 可能是 0 或者不准确
+    // 我们优先使用 response header 中的 Content-Length，如果也没有，则无法计算进度
+    let total_size = match res.content_length() {
+        Some(len) => len + offset,
+        None if job.size > 0 => job.size as u64,
+        None => 0,
+    };
+
+    let mut stream = res.bytes_stream();
+    let mut file = open_part_file(&part_path, offset)?;
+    let mut downloaded: u64 = offset;
+
+    // 边下载边计算 MD5，避免下载完再读一遍文件
+    let mut hasher = match (job.verifiable(), offset) {
+        (false, _) => None,
+        (true, 0) => Some(Md5::new()),
+        (true, _) => Some(hash_prefix(&part_path, offset).await?),
+    };
+    let mut last_saved: u64 = offset;
+
+    tracker.set_total(total_size);
+    tracker.reset(offset);
+    emit_progress(app, job, tracker.snapshot(), "downloading");
+
+    while let Some(item) = stream.next().await {
+        let chunk = match item {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // 中断时记录已写入的字节数，下次从这里继续
+                if job.resumable() {
+                    part_state.downloaded = downloaded;
+                    part_state.save(&state_path)?;
+                }
+                return Err(FetchError::Retryable(format!("下载流中断: {}", e)));
+            }
+        };
+        // 限速: 令牌不足时暂停读取，由 TCP 反压降低实际速度
+        throttle.consume(chunk.len() as u64).await;
+        file.write_all(&chunk)
+            .map_err(|e| format!("写入失败: {}", e))?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&chunk);
+        }
+
+        downloaded += chunk.len() as u64;
+
+        // 定期更新续传记录，防止程序意外退出
+        if job.resumable() && downloaded - last_saved >= SAVE_INTERVAL {
+            part_state.downloaded = downloaded;
+            part_state.save(&state_path)?;
+            last_saved = downloaded;
+        }
+
+        if let Some(snapshot) = tracker.advance(chunk.len() as u64) {
+            emit_progress(app, job, snapshot, "downloading");
+        }
+    }
+
+    file.flush().map_err(|e| format!("写入失败: {}", e))?;
+
+    // 连接被提前关闭时数据不完整，保存进度后重试
+    if total_size > 0 && downloaded < total_size {
+        if job.resumable() {
+            part_state.downloaded = downloaded;
+            part_state.save(&state_path)?;
+        }
+        return Err(FetchError::Retryable(format!(
+            "下载数据不完整: {}/{} 字节",
+            downloaded, total_size
+        )));
+    }
+    Ok(hasher.map(|h| hex::encode(h.finalize())))
+}
+
+async fn fetch_segmented(
+    client: &Client,
+    url: &str,
+    job: &DownloadJob,
+    total: u64,
+    throttle: &Throttle,
+    tracker: &mut ProgressTracker,
+    app: &AppHandle,
+) -> Result<(), FetchError> {
+    let part_path = part_path(&job.save_path);
+    let state_path = state_path(&job.save_path);
+    let mut part_state = PartState::new(job.file_id, &job.etag, job.size);
+
+    // 优先沿用上次的分段；上次是单线程下载时，把已完成的前缀当作一段
+    match load_state(&part_path, &state_path, &part_state) {
+        Some((saved, on_disk)) if !saved.segments.is_empty() && on_disk == total => {
+            part_state.segments = saved.segments;
+        }
+        Some((saved, on_disk)) => {
+            let prefix = saved.downloaded.min(on_disk).min(total);
+            if prefix > 0 {
+                part_state.segments.push(Segment {
+                    start: 0,
+                    end: prefix - 1,
+                    done: prefix,
+                });
+            }
+            if prefix < total {
+                part_state
+                    .segments
+                    .extend(plan_segments(prefix, total, job.connections));
+            }
+        }
+        None => part_state.segments = plan_segments(0, total, job.connections),
+    }
+    part_state.update_prefix();
+
+    // 预分配完整大小，各分段直接写入自己的偏移
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&part_path)
+        .map_err(|e| format!("创建文件失败: {}", e))?;
+    file.set_len(total)
+        .map_err(|e| format!("预分配文件失败: {}", e))?;
+    drop(file);
+
+    let mut downloaded: u64 = part_state.segments.iter().map(|s| s.done).sum();
+    let mut last_saved = downloaded;
+    info!(
+        "分段下载 {} ({} 段, 已完成 {} 字节)",
+        job.file_name,
+        part_state.segments.len(),
+        downloaded
+    );
+    tracker.set_total(total);
+    tracker.reset(downloaded);
+    emit_progress(app, job, tracker.snapshot(), "downloading");
+
+    // 各分段把写入的字节数汇报到同一个通道，由这里统一计算进度
+    // 分段和进度汇总在同一个 future 里并发执行，任务被中止时会一起停止
+    let (tx, mut rx) = mpsc::unbounded_channel::<(usize, u64)>();
+    let workers = join_all(
+        part_state
+            .segments
+            .iter()
+            .enumerate()
+            .filter(|(_, seg)| !seg.is_complete())
+            .map(|(index, seg)| {
+                fetch_segment(
+                    client,
+                    url,
+                    &part_path,
+                    index,
+                    seg.clone(),
+                    throttle,
+                    tx.clone(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    drop(tx);
+
+    let collect = async {
+        while let Some((index, n)) = rx.recv().await {
+            part_state.segments[index].done += n;
+            downloaded += n;
+
+            if downloaded - last_saved >= SAVE_INTERVAL {
+                part_state.update_prefix();
+                part_state.save(&state_path)?;
+                last_saved = downloaded;
+            }
+            if let Some(snapshot) = tracker.advance(n) {
+                emit_progress(app, job, snapshot, "downloading");
+            }
+        }
+        Ok::<(), String>(())
+    };
+
+    let (results, collected) = tokio::join!(workers, collect);
+    collected?;
+
+    // 所有分段都已结束，收集错误; 只要有一段无法重试就整体失败
+    let errors: Vec<FetchError> = results.into_iter().filter_map(|r| r.err()).collect();
+    if !errors.is_empty() {
+        part_state.update_prefix();
+        part_state.save(&state_path)?;
+        let fatal = errors.iter().any(|e| matches!(e, FetchError::Fatal(_)));
+        let message = errors
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join("; ");
+        return Err(if fatal {
+            FetchError::Fatal(message)
+        } else {
+            FetchError::Retryable(message)
+        });
+    }
+
+    // 下载完成，保存最终记录，校验通过后再改名
+    part_state.update_prefix();
+    Ok(part_state.save(&state_path)?)
+}
+
+async fn fetch_segment(
+    client: &Client,
+    url: &str,
+    part_path: &Path,
+    index: usize,
+    seg: Segment,
+    throttle: &Throttle,
+    tx: mpsc::UnboundedSender<(usize, u64)>,
+) -> Result<(), FetchError> {
+    let from = seg.start + seg.done;
+    let res = client
+        .get(url)
+        .header(RANGE, format!("bytes={}-{}", from, seg.end))
+        .send()
+        .await
+        .map_err(|e| FetchError::Retryable(format!("分段 {} 请求失败: {}", index, e)))?;
+    check_status(res.status())?;
+    if res.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(FetchError::Fatal(format!(
+            "分段 {} 未返回部分内容: {}",
+            index,
+            res.status()
+        )));
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .await
+        .map_err(|e| format!("打开临时文件失败: {}", e))?;
+    file.seek(SeekFrom::Start(from))
+        .await
+        .map_err(|e| format!("定位临时文件失败: {}", e))?;
+
+    let mut remaining = seg.end + 1 - from;
+    let mut stream = res.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let chunk =
+            item.map_err(|e| FetchError::Retryable(format!("分段 {} 下载流中断: {}", index, e)))?;
+        throttle.consume(chunk.len() as u64).await;
+        // 防止服务器多返回数据写进下一段
+        let n = (chunk.len() as u64).min(remaining) as usize;
+        file.write_all(&chunk[..n])
+            .await
+            .map_err(|e| format!("写入失败: {}", e))?;
+        // 等数据真正落盘后再汇报，保证续传记录不超前
+        file.flush().await.map_err(|e| format!("写入失败: {}", e))?;
+        remaining -= n as u64;
+        let _ = tx.send((index, n as u64));
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    if remaining > 0 {
+        return Err(FetchError::Retryable(format!(
+            "分段 {} 数据不完整，缺少 {} 字节",
+            index, remaining
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_path_joins_plain_names() {
+        let root = Path::new("downloads");
+        assert_eq!(
+            local_path(root, "a/b.txt").unwrap(),
+            root.join("a").join("b.txt")
+        );
+    }
+
+    #[test]
+    fn local_path_rejects_escapes() {
+        let root = Path::new("downloads");
+        for relative in ["..", "a/../b", "/etc/passwd", "a//b", ".", ""] {
+            assert!(local_path(root, relative).is_err(), "{}", relative);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{info, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::progress::{ProgressTracker, Snapshot};
+use crate::upload::{self, ConflictPolicy, UploadOptions, UploadOutcome};
+use crate::{AppState, RemoteFolders};
+
+// 文件夹上传时挑选文件的规则，模式匹配相对于所选文件夹的路径 (用 / 分隔)
+pub struct UploadFilter {
+    include: Option<GlobSet>, // 为空时包含所有文件
+    exclude: GlobSet,
+    skip_hidden: bool,
+}
+
+impl UploadFilter {
+    pub fn new(include: &[String], exclude: &[String], skip_hidden: bool) -> Result<Self, String> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_globs(include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: build_globs(exclude)?,
+            skip_hidden,
+        })
+    }
+
+    // 模式同时匹配相对路径和名字，方便写 "*.jpg"、"node_modules" 这样的模式
+    fn matches(set: &GlobSet, entry: &DirEntry, relative: &str) -> bool {
+        set.is_match(relative) || set.is_match(entry.file_name().to_string_lossy().as_ref())
+    }
+
+    // 目录: 被排除或隐藏时整个跳过
+    fn keep_dir(&self, entry: &DirEntry, relative: &str) -> bool {
+        if self.skip_hidden && is_hidden(entry) {
+            return false;
+        }
+        !Self::matches(&self.exclude, entry, relative)
+    }
+
+    fn keep_file(&self, entry: &DirEntry, relative: &str) -> bool {
+        if self.skip_hidden && is_hidden(entry) {
+            return false;
+        }
+        if Self::matches(&self.exclude, entry, relative) {
+            return false;
+        }
+        self.include
+            .as_ref()
+            .is_none_or(|set| Self::matches(set, entry, relative))
+    }
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| format!("无效的匹配模式 {}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+// 以 . 开头的文件，Windows 上还包括带隐藏属性的文件
+fn is_hidden(entry: &DirEntry) -> bool {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if let Ok(meta) = entry.metadata() {
+            return meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    false
+}
+
+// 本地文件夹中的一项，relative 为相对路径 (用 / 分隔)
+struct LocalEntry {
+    path: PathBuf,
+    relative: String,
+    is_dir: bool,
+    size: u64,
+}
+
+// 遍历本地文件夹，父目录排在子项之前
+fn walk_local(root: &Path, filter: &UploadFilter) -> Result<Vec<LocalEntry>, String> {
+    let relative = |path: &Path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+
+    let mut entries = Vec::new();
+    let walker = WalkDir::new(root)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !e.file_type().is_dir() || filter.keep_dir(e, &relative(e.path())));
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("读取目录失败: {}", e))?;
+        let rel = relative(entry.path());
+        if entry.file_type().is_dir() {
+            entries.push(LocalEntry {
+                path: entry.path().to_path_buf(),
+                relative: rel,
+                is_dir: true,
+                size: 0,
+            });
+        } else if entry.file_type().is_file() && filter.keep_file(&entry, &rel) {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            entries.push(LocalEntry {
+                path: entry.path().to_path_buf(),
+                relative: rel,
+                is_dir: false,
+                size,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+// 文件夹上传的整体进度事件
+#[derive(Clone, Serialize)]
+struct FolderUploadPayload {
+    id: String, // 本地文件夹路径
+    files_total: usize,
+    files_done: usize,
+    files_failed: usize,
+    status: String, // "uploading", "finished", "error"
+    #[serde(flatten)]
+    snapshot: Snapshot,
+}
+
+// 上传结束后返回给前端的汇总
+#[derive(Clone, Serialize, Debug)]
+pub struct FolderUploadSummary {
+    pub folder_id: i64, // 网盘中新建的文件夹
+    pub files_total: usize,
+    pub files_done: usize, // 包括跳过的文件
+    pub files_skipped: usize,
+    pub files_failed: usize,
+    pub errors: Vec<String>,
+}
+
+struct GroupProgress {
+    files_done: usize,
+    files_failed: usize,
+    finished_bytes: u64, // 已结束的文件的总字节数
+    tracker: ProgressTracker,
+}
+
+// 汇总文件夹中各个文件的上传进度，文件逐个上传
+pub struct FolderUpload {
+    id: String,
+    files_total: usize,
+    progress: Mutex<GroupProgress>,
+}
+
+impl FolderUpload {
+    fn new(id: &str, files_total: usize, bytes_total: u64) -> Self {
+        Self {
+            id: id.to_string(),
+            files_total,
+            progress: Mutex::new(GroupProgress {
+                files_done: 0,
+                files_failed: 0,
+                finished_bytes: 0,
+                tracker: ProgressTracker::new(bytes_total, 0),
+            }),
+        }
+    }
+
+    fn emit(&self, app: &AppHandle, progress: &GroupProgress, snapshot: Snapshot) {
+        let status = if progress.files_done + progress.files_failed < self.files_total {
+            "uploading"
+        } else if progress.files_failed == 0 {
+            "finished"
+        } else {
+            "error"
+        };
+        app.emit(
+            "folder-upload-progress",
+            FolderUploadPayload {
+                id: self.id.clone(),
+                files_total: self.files_total,
+                files_done: progress.files_done,
+                files_failed: progress.files_failed,
+                status: status.to_string(),
+                snapshot,
+            },
+        )
+        .unwrap_or(());
+    }
+
+    // 当前文件已上传 file_bytes 字节
+    pub fn update(&self, app: &AppHandle, file_bytes: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        let done = progress.finished_bytes + file_bytes;
+        if let Some(snapshot) = progress.tracker.set_done(done) {
+            self.emit(app, &progress, snapshot);
+        }
+    }
+
+    fn file_finished(&self, app: &AppHandle, size: u64, ok: bool) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.finished_bytes += size;
+        if ok {
+            progress.files_done += 1;
+        } else {
+            progress.files_failed += 1;
+        }
+        let done = progress.finished_bytes;
+        progress.tracker.set_done(done);
+        let snapshot = progress.tracker.snapshot();
+        self.emit(app, &progress, snapshot);
+    }
+}
+
+// 在网盘中重建本地文件夹的目录结构，并把文件逐个上传到对应的目录
+pub async fn run(
+    app: &AppHandle,
+    parent_file_id: i64,
+    local_dir: &str,
+    filter: &UploadFilter,
+    policy: ConflictPolicy,
+) -> Result<FolderUploadSummary, String> {
+    let state = app.state::<AppState>();
+    let root = Path::new(local_dir);
+    let folder_name = root
+        .file_name()
+        .ok_or("无效的文件夹路径")?
+        .to_string_lossy()
+        .to_string();
+
+    info!("开始遍历本地文件夹: {}", local_dir);
+    let entries = walk_local(root, filter)?;
+    let files_total = entries.iter().filter(|e| !e.is_dir).count();
+    let bytes_total = entries.iter().map(|e| e.size).sum();
+    info!("文件夹 {} 共 {} 个文件", folder_name, files_total);
+
+    let group = FolderUpload::new(local_dir, files_total, bytes_total);
+    group.emit(
+        app,
+        &group.progress.lock().unwrap(),
+        Snapshot {
+            bytes_total,
+            ..Snapshot::default()
+        },
+    );
+
+    let folder_id = crate::create_remote_folder(&state.api, parent_file_id, &folder_name).await?;
+    let mut folders = RemoteFolders::created(folder_id);
+
+    let mut errors = Vec::new();
+    let mut files_skipped = 0;
+    for entry in entries {
+        if entry.is_dir {
+            // 空文件夹也要建出来
+            if let Err(e) = folders.get_or_create(&state.api, &entry.relative).await {
+                warn!("创建文件夹失败: {} - {}", entry.relative, e);
+                errors.push(format!("{}: {}", entry.relative, e));
+            }
+            continue;
+        }
+        let parent_rel = entry
+            .relative
+            .rsplit_once('/')
+            .map(|(p, _)| p)
+            .unwrap_or("");
+        let parent_id = match folders.get_or_create(&state.api, parent_rel).await {
+            Ok(id) => id,
+            Err(e) => {
+                errors.push(format!("{}: {}", entry.relative, e));
+                group.file_finished(app, entry.size, false);
+                continue;
+            }
+        };
+
+        let path = entry.path.to_string_lossy().to_string();
+        let options = UploadOptions {
+            policy,
+            part_size: None,
+        };
+        let result = upload::run(app, parent_id, &path, options, Some(&group)).await;
+        match &result {
+            Ok(UploadOutcome::Skipped) => files_skipped += 1,
+            Ok(_) => {}
+            Err(e) => errors.push(format!("{}: {}", entry.relative, e)),
+        }
+        group.file_finished(app, entry.size, result.is_ok());
+    }
+
+    let progress = group.progress.lock().unwrap();
+    info!(
+        "文件夹上传结束: {} (成功 {}, 失败 {})",
+        folder_name, progress.files_done, progress.files_failed
+    );
+    Ok(FolderUploadSummary {
+        folder_id,
+        files_total,
+        files_done: progress.files_done,
+        files_skipped,
+        files_failed: progress.files_failed,
+        errors,
+    })
+}
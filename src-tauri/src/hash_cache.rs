@@ -0,0 +1,135 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Runtime};
+use tauri_plugin_store::{Store, StoreExt};
+use walkdir::WalkDir;
+
+// 本地文件的 MD5 缓存，以规范化后的路径为键，保存在应用数据目录
+const HASH_STORE: &str = "hashes.json";
+// 预先计算时每处理这么多个文件保存一次
+const SAVE_EVERY: usize = 50;
+
+// 大小和修改时间都没变时认为内容没变
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct HashEntry {
+    size: u64,
+    mtime: SystemTime, // 保留纳秒，同一秒内改写的文件也能区分
+    md5: String,
+}
+
+// 文件大小和修改时间 (平台支持的最高精度)
+pub fn file_stamp(file_path: &str) -> Result<(u64, SystemTime), String> {
+    let meta = std::fs::metadata(file_path).map_err(|e| format!("读取文件信息失败: {}", e))?;
+    Ok((meta.len(), meta.modified().unwrap_or(UNIX_EPOCH)))
+}
+
+fn cache_key(file_path: &str) -> String {
+    std::fs::canonicalize(file_path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| file_path.to_string())
+}
+
+fn lookup<R: Runtime>(store: &Store<R>, key: &str, size: u64, mtime: SystemTime) -> Option<String> {
+    let entry: HashEntry = serde_json::from_value(store.get(key)?).ok()?;
+    (entry.size == size && entry.mtime == mtime).then_some(entry.md5)
+}
+
+// 计算文件 MD5 并写入缓存 (不保存到磁盘)；返回 MD5 和文件大小
+async fn hash_into<R: Runtime>(store: &Store<R>, file_path: &str) -> Result<(String, u64), String> {
+    let key = cache_key(file_path);
+    let (size, mtime) = file_stamp(file_path)?;
+    if let Some(md5) = lookup(store, &key, size, mtime) {
+        return Ok((md5, size));
+    }
+
+    let (md5, len) = crate::calculate_file_md5(file_path.to_string()).await?;
+    // 计算期间文件被修改过时结果不可靠，不缓存
+    if file_stamp(file_path)? == (size, mtime) && len == size {
+        store.set(
+            key,
+            json!(HashEntry {
+                size,
+                mtime,
+                md5: md5.clone()
+            }),
+        );
+    } else {
+        store.delete(&key);
+    }
+    Ok((md5, len))
+}
+
+// 取文件的 MD5，文件没变过时直接使用缓存
+pub async fn file_md5(app: &AppHandle, file_path: &str) -> Result<(String, u64), String> {
+    let store = app.store(HASH_STORE).map_err(|e| e.to_string())?;
+    let (size, mtime) = file_stamp(file_path)?;
+    if let Some(md5) = lookup(&store, &cache_key(file_path), size, mtime) {
+        info!("使用缓存的 MD5: {}", file_path);
+        return Ok((md5, size));
+    }
+
+    let result = hash_into(&store, file_path).await?;
+    store.save().map_err(|e| e.to_string())?;
+    Ok(result)
+}
+
+// 预先计算进度事件
+#[derive(Clone, Serialize)]
+struct PrehashPayload {
+    id: String, // 文件夹路径
+    files_total: usize,
+    files_done: usize,
+    files_failed: usize,
+    current: String,
+    status: String, // "hashing", "finished"
+}
+
+// 在后台计算文件夹中所有文件的 MD5，之后上传时不必再读一遍
+pub async fn prehash_dir(app: &AppHandle, dir: &str) -> Result<(), String> {
+    let store = app.store(HASH_STORE).map_err(|e| e.to_string())?;
+    let files: Vec<String> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_string_lossy().to_string())
+        .collect();
+
+    let mut payload = PrehashPayload {
+        id: dir.to_string(),
+        files_total: files.len(),
+        files_done: 0,
+        files_failed: 0,
+        current: String::new(),
+        status: "hashing".to_string(),
+    };
+    info!("开始预先计算 MD5: {} ({} 个文件)", dir, files.len());
+
+    for (i, file) in files.iter().enumerate() {
+        payload.current = Path::new(file)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        app.emit("prehash-progress", payload.clone()).unwrap_or(());
+
+        match hash_into(&store, file).await {
+            Ok(_) => payload.files_done += 1,
+            Err(e) => {
+                warn!("计算 MD5 失败: {} - {}", file, e);
+                payload.files_failed += 1;
+            }
+        }
+        if (i + 1) % SAVE_EVERY == 0 {
+            store.save().map_err(|e| e.to_string())?;
+        }
+    }
+    store.save().map_err(|e| e.to_string())?;
+
+    info!("预先计算 MD5 完成: {}", dir);
+    payload.current = String::new();
+    payload.status = "finished".to_string();
+    app.emit("prehash-progress", payload).unwrap_or(());
+    Ok(())
+}
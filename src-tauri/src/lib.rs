@@ -1,28 +1,46 @@
-use futures_util::StreamExt;
 use log::{debug, error, info, warn};
 use md5::{Digest, Md5};
-use regex::Regex;
 use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{Read, Write}; // 用于文件分块读取
+use std::io::Read; // 用于文件分块读取
 use std::sync::Mutex;
-use tauri::{Emitter, State, Window};
+use tauri::State;
 use tauri_plugin_store::StoreExt;
-use tokio::io::AsyncReadExt;
 use uuid::Uuid; // 异步读取
 
+mod accounts;
+mod aria2;
+mod buffers;
+mod download;
+mod folder_upload;
+mod hash_cache;
 mod models;
+mod progress;
+mod rapid;
+mod resolver;
+mod sync;
+mod tasks;
+#[cfg(test)]
+mod test_server;
+mod throttle;
+mod transfer;
+mod upload;
+mod url_upload;
+mod watch;
 use models::*;
 
-pub struct AppState {
+// 一个账号的登录会话: 带 Cookie 的 HTTP 客户端、Token 和设备 UUID
+// 其他账号 (见 accounts) 使用各自的会话，下载队列、限速等仍由 AppState 统一管理
+pub struct ApiSession {
     client: Client,
     token: Mutex<String>,
     login_uuid: String,
 }
 
-impl AppState {
+impl ApiSession {
     fn new() -> Self {
         let client = Client::builder()
             .cookie_store(true)
@@ -41,6 +59,30 @@ impl AppState {
     }
 }
 
+pub struct AppState {
+    api: ApiSession, // 当前登录的账号
+    downloads: tasks::DownloadManager,
+    resolver: resolver::DownloadUrlResolver,
+    limits: throttle::BandwidthLimits,
+    upload_conflicts: upload::ConflictPrompts,
+    uploads: upload::ActiveUploads,
+    watcher: watch::FolderWatcher,
+}
+
+impl AppState {
+    fn new() -> Self {
+        Self {
+            api: ApiSession::new(),
+            downloads: tasks::DownloadManager::new(),
+            resolver: resolver::DownloadUrlResolver::new(),
+            limits: throttle::BandwidthLimits::new(),
+            upload_conflicts: upload::ConflictPrompts::new(),
+            uploads: upload::ActiveUploads::new(),
+            watcher: watch::FolderWatcher::new(),
+        }
+    }
+}
+
 fn add_auth_headers(request: RequestBuilder, token: &str, login_uuid: &str) -> RequestBuilder {
     request
         .header("authorization", token)
@@ -71,6 +113,26 @@ async fn login(
 ) -> Result<String, String> {
     info!("尝试登录用户: {}", username);
 
+    let token_str = sign_in(&state.api, &username, &password).await?;
+    *state.api.token.lock().unwrap() = token_str.clone();
+
+    let store = app.store("auth.json").map_err(|e| e.to_string())?;
+    store.set(
+        "credentials",
+        json!({
+            "username": username,
+            "password": password,
+            "token": token_str
+        }),
+    );
+    store.save().map_err(|e| e.to_string())?;
+
+    info!("登录成功并已保存凭证");
+    Ok("登录成功".to_string())
+}
+
+// 用账号密码登录，返回带 Bearer 前缀的 Token
+async fn sign_in(api: &ApiSession, username: &str, password: &str) -> Result<String, String> {
     let url = "https://www.123pan.com/b/api/user/sign_in";
     let payload = json!({
         "type": 1,
@@ -78,8 +140,8 @@ async fn login(
         "password": password
     });
 
-    let req = state.client.post(url).json(&payload);
-    let req = add_auth_headers(req, "", &state.login_uuid);
+    let req = api.client.post(url).json(&payload);
+    let req = add_auth_headers(req, "", &api.login_uuid);
 
     let res = req.send().await.map_err(|e| {
         error!("登录网络请求失败: {}", e);
@@ -93,27 +155,10 @@ async fn login(
         return Err(json_res.message);
     }
 
-    if let Some(data) = json_res.data {
-        let token_str = format!("Bearer {}", data.token);
-        let mut token = state.token.lock().unwrap();
-        *token = token_str.clone();
-
-        let store = app.store("auth.json").map_err(|e| e.to_string())?;
-        store.set(
-            "credentials",
-            json!({
-                "username": username,
-                "password": password,
-                "token": token_str
-            }),
-        );
-        store.save().map_err(|e| e.to_string())?;
-
-        info!("登录成功并已保存凭证");
-        return Ok("登录成功".to_string());
-    }
-
-    Err("未知登录错误".to_string())
+    json_res
+        .data
+        .map(|data| format!("Bearer {}", data.token))
+        .ok_or_else(|| "未知登录错误".to_string())
 }
 
 // 获取文件列表
@@ -122,10 +167,42 @@ async fn get_file_list(
     parent_file_id: i64,
     state: State<'_, AppState>,
 ) -> Result<Vec<FileInfo>, String> {
+    list_folder(&state.api, parent_file_id).await
+}
+
+// 一个远程条目及其相对于遍历起点的路径 (以 / 分隔)
+struct RemoteEntry {
+    path: String,
+    info: FileInfo,
+}
+
+// 递归遍历远程文件夹，父文件夹总是排在其内容之前
+async fn walk_folder(api: &ApiSession, folder_id: i64) -> Result<Vec<RemoteEntry>, String> {
+    let mut entries = Vec::new();
+    let mut pending = vec![(folder_id, String::new())];
+
+    while let Some((parent_id, prefix)) = pending.pop() {
+        for info in list_folder(api, parent_id).await? {
+            let path = if prefix.is_empty() {
+                info.file_name.clone()
+            } else {
+                format!("{}/{}", prefix, info.file_name)
+            };
+            if info.file_type == 1 {
+                pending.push((info.file_id, path.clone()));
+            }
+            entries.push(RemoteEntry { path, info });
+        }
+    }
+    Ok(entries)
+}
+
+// 分页获取某个文件夹下的全部条目
+async fn list_folder(api: &ApiSession, parent_file_id: i64) -> Result<Vec<FileInfo>, String> {
     debug!("正在获取目录列表: {}", parent_file_id);
 
     let url = "https://www.123pan.com/b/api/file/list/new";
-    let token = state.token.lock().unwrap().clone();
+    let token = api.token.lock().unwrap().clone();
 
     let mut all_files: Vec<FileInfo> = Vec::new();
     let mut page = 1;
@@ -152,8 +229,8 @@ async fn get_file_list(
             ("OnlyLookAbnormalFile", "0"),
         ];
 
-        let req = state.client.get(url).query(&params);
-        let req = add_auth_headers(req, &token, &state.login_uuid);
+        let req = api.client.get(url).query(&params);
+        let req = add_auth_headers(req, &token, &api.login_uuid);
 
         let res = req.send().await.map_err(|e| e.to_string())?;
         let json_res: ApiResponse<FileListData> = res.json().await.map_err(|e| e.to_string())?;
@@ -212,15 +289,15 @@ async fn try_auto_login(app: tauri::AppHandle, state: State<'_, AppState>) -> Re
                 ("OnlyLookAbnormalFile", "0"),
             ];
 
-            let req = state.client.get(check_url).query(&params);
-            let req = add_auth_headers(req, &saved_token, &state.login_uuid);
+            let req = state.api.client.get(check_url).query(&params);
+            let req = add_auth_headers(req, &saved_token, &state.api.login_uuid);
 
             let res = req.send().await;
 
             if let Ok(response) = res {
                 if let Ok(json) = response.json::<serde_json::Value>().await {
                     if json.get("code").and_then(|c| c.as_i64()) == Some(0) {
-                        let mut token_lock = state.token.lock().unwrap();
+                        let mut token_lock = state.api.token.lock().unwrap();
                         *token_lock = saved_token;
                         info!("自动登录：Token 有效，复用成功");
                         return Ok(true);
@@ -234,19 +311,9 @@ async fn try_auto_login(app: tauri::AppHandle, state: State<'_, AppState>) -> Re
         // 策略 B: 重新登录
         info!("自动登录：Token 失效或校验未通过，使用密码重新登录...");
 
-        let url = "https://www.123pan.com/b/api/user/sign_in";
-        let payload = json!({"type": 1, "passport": creds.username, "password": creds.password});
-
-        let req = state.client.post(url).json(&payload);
-        let req = add_auth_headers(req, "", &state.login_uuid);
-
-        let res = req.send().await.map_err(|e| e.to_string())?;
-        let json_res: LoginResponse = res.json().await.map_err(|e| e.to_string())?;
-
-        if json_res.code == 200 {
-            if let Some(data) = json_res.data {
-                let new_token_str = format!("Bearer {}", data.token);
-                let mut token_lock = state.token.lock().unwrap();
+        match sign_in(&state.api, &creds.username, &creds.password).await {
+            Ok(new_token_str) => {
+                let mut token_lock = state.api.token.lock().unwrap();
                 *token_lock = new_token_str.clone();
 
                 store.set(
@@ -263,8 +330,7 @@ async fn try_auto_login(app: tauri::AppHandle, state: State<'_, AppState>) -> Re
                 info!("自动登录：密码重登成功");
                 return Ok(true);
             }
-        } else {
-            error!("自动登录：密码重登失败 Code: {}", json_res.code);
+            Err(e) => error!("自动登录：密码重登失败: {}", e),
         }
     }
     Ok(false)
@@ -277,7 +343,7 @@ async fn logout(app: tauri::AppHandle, state: State<'_, AppState>) -> Result<(),
     store.delete("credentials");
     store.save().map_err(|e| e.to_string())?;
 
-    let mut token = state.token.lock().unwrap();
+    let mut token = state.api.token.lock().unwrap();
     *token = String::new();
 
     Ok(())
@@ -285,14 +351,20 @@ async fn logout(app: tauri::AppHandle, state: State<'_, AppState>) -> Result<(),
 
 #[derive(Clone, serde::Serialize)]
 struct ProgressPayload {
-    id: String,
-    progress: u64,
-    speed: String,
+    id: String, // 任务 ID
+    file_id: i64,
+    // "queued", "downloading", "restarted", "reconnecting", "verifying", "verify-failed",
+    // "paused", "finished", "error", "cancelled"
     status: String,
+    #[serde(flatten)]
+    snapshot: progress::Snapshot, // progress, speed, eta, bytes_done, bytes_total
+    error: Option<String>, // status 为 "error" 时的原因
 }
 
+// 加入下载队列，返回任务 ID
 #[tauri::command]
 async fn download_file(
+    app: tauri::AppHandle,
     file_id: i64,
     file_name: String,
     file_type: i32,
@@ -300,151 +372,200 @@ async fn download_file(
     s3_key_flag: String,
     size: i64,
     save_path: String,
-    window: Window,
+    connections: Option<u32>,
+    retry_on_mismatch: Option<bool>,
+    max_retries: Option<u32>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    info!("开始下载: {} (Type: {})", file_name, file_type);
+) -> Result<String, String> {
+    let job = download::DownloadJob {
+        id: String::new(),
+        file_id,
+        file_name,
+        file_type,
+        etag,
+        s3_key_flag,
+        size,
+        save_path,
+        connections: connections.unwrap_or(1).clamp(1, download::MAX_CONNECTIONS),
+        retry_on_mismatch: retry_on_mismatch.unwrap_or(false),
+        max_retries: max_retries.unwrap_or(download::DEFAULT_MAX_RETRIES),
+        group: None,
+    };
+    Ok(state.downloads.enqueue(&app, job))
+}
 
-    let client = &state.client;
-    let token = state.token.lock().unwrap().clone();
+// 按目录结构下载整个文件夹: 在 save_dir 下重建目录，每个文件作为单独的任务排队
+// 返回文件夹任务 ID，整体进度通过 folder-progress 事件报告
+#[tauri::command]
+async fn download_folder(
+    app: tauri::AppHandle,
+    folder_id: i64,
+    folder_name: String,
+    save_dir: String,
+    connections: Option<u32>,
+    retry_on_mismatch: Option<bool>,
+    max_retries: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    info!("开始遍历文件夹: {}", folder_name);
+    let root = download::local_path(std::path::Path::new(&save_dir), &folder_name)?;
+    std::fs::create_dir_all(&root).map_err(|e| format!("创建目录失败: {}", e))?;
+
+    let mut jobs = Vec::new();
+    for entry in walk_folder(&state.api, folder_id).await? {
+        let local_path = download::local_path(&root, &entry.path)?;
+        if entry.info.file_type == 1 {
+            std::fs::create_dir_all(&local_path).map_err(|e| format!("创建目录失败: {}", e))?;
+            continue;
+        }
+        jobs.push(download::DownloadJob {
+            id: String::new(),
+            file_id: entry.info.file_id,
+            file_name: entry.info.file_name,
+            file_type: 0,
+            etag: entry.info.etag.unwrap_or_default(),
+            s3_key_flag: entry.info.s3_key_flag.unwrap_or_else(|| "0".to_string()),
+            size: entry.info.size,
+            save_path: local_path.to_string_lossy().to_string(),
+            connections: connections.unwrap_or(1).clamp(1, download::MAX_CONNECTIONS),
+            retry_on_mismatch: retry_on_mismatch.unwrap_or(false),
+            max_retries: max_retries.unwrap_or(download::DEFAULT_MAX_RETRIES),
+            group: None,
+        });
+    }
 
-    // 步骤 1: 根据类型选择 API 和 Payload
-    let intermediate_url;
+    info!("文件夹 {} 共 {} 个文件", folder_name, jobs.len());
+    Ok(state.downloads.enqueue_folder(&app, folder_id, jobs))
+}
 
-    if file_type == 1 {
-        // --- 文件夹逻辑 ---
-        let batch_url = "https://www.123pan.com/a/api/file/batch_download_info";
-        let payload = json!({
-            "fileIdList": [{ "fileId": file_id }]
-        });
+#[tauri::command]
+async fn list_downloads(state: State<'_, AppState>) -> Result<Vec<tasks::TaskInfo>, String> {
+    Ok(state.downloads.list())
+}
 
-        let req = client.post(batch_url).json(&payload);
-        let req = add_auth_headers(req, &token, &state.login_uuid);
-        let res = req.send().await.map_err(|e| e.to_string())?;
-        let info_res: DownloadInfoResponse = res.json().await.map_err(|e| e.to_string())?;
+#[tauri::command]
+async fn pause_download(
+    app: tauri::AppHandle,
+    job_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.downloads.pause(&app, &job_id)
+}
 
-        if info_res.code != 0 {
-            return Err(format!("获取打包下载链接失败: {}", info_res.message));
-        }
-        intermediate_url = info_res.data.map(|d| d.download_url).ok_or("链接为空")?;
-    } else {
-        // --- 单文件逻辑 ---
-        let info_url = "https://www.123pan.com/a/api/file/download_info";
-        let payload = json!({
-            "driveId": 0,
-            "fileId": file_id,
-            "etag": etag,
-            "s3keyFlag": s3_key_flag,
-            "type": 0,
-            "fileName": file_name,
-            "size": size
-        });
+#[tauri::command]
+async fn resume_download(
+    app: tauri::AppHandle,
+    job_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.downloads.resume(&app, &job_id)
+}
 
-        let req = client.post(info_url).json(&payload);
-        let req = add_auth_headers(req, &token, &state.login_uuid);
-        let res = req.send().await.map_err(|e| e.to_string())?;
-        let info_res: DownloadInfoResponse = res.json().await.map_err(|e| e.to_string())?;
+#[tauri::command]
+async fn cancel_download(
+    app: tauri::AppHandle,
+    job_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.downloads.cancel(&app, &job_id)
+}
 
-        if info_res.code != 0 {
-            return Err(format!("获取下载链接失败: {}", info_res.message));
-        }
-        intermediate_url = info_res.data.map(|d| d.download_url).ok_or("链接为空")?;
-    }
+// 清除已完成和已取消的任务，返回清除的数量
+#[tauri::command]
+async fn clear_downloads(state: State<'_, AppState>) -> Result<usize, String> {
+    Ok(state.downloads.clear_finished())
+}
 
-    // 步骤 2: 解析中间页 (复用已有逻辑)
-    let no_redirect_client = Client::builder()
-        .redirect(reqwest::redirect::Policy::none())
-        .user_agent("123pan/v2.4.0(Android_7.1.2;Xiaomi)")
-        .build()
-        .map_err(|e| e.to_string())?;
+// 调整任务在队列中的位置，0 为最高优先级
+#[tauri::command]
+async fn reorder_download(
+    job_id: String,
+    position: usize,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.downloads.reorder(&job_id, position)
+}
 
-    let html_res = no_redirect_client
-        .get(&intermediate_url)
-        .send()
-        .await
-        .map_err(|e| format!("中间页请求失败: {}", e))?;
-
-    // 检查 Location 头
-    let final_download_url = if let Some(loc) = html_res.headers().get("location") {
-        loc.to_str().unwrap_or_default().to_string()
-    } else {
-        let html_text = html_res
-            .text()
-            .await
-            .map_err(|e| format!("读取跳转页失败: {}", e))?;
-        let re = Regex::new(r"href='(https?://[^']+)'").map_err(|e| format!("正则错误: {}", e))?;
-        re.captures(&html_text)
-            .and_then(|caps| caps.get(1).map(|m| m.as_str().to_string()))
-            .ok_or("无法解析下载地址")?
+#[tauri::command]
+async fn set_max_downloads(
+    app: tauri::AppHandle,
+    max_active: usize,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.downloads.set_max_active(&app, max_active);
+    Ok(())
+}
+
+// 设置限速 (字节/秒，0 为不限速)，direction 为 "download" 或 "upload"
+// task_id 为空时设置全局限速，否则只限制该任务 (下载任务 ID 或上传的文件路径)
+#[tauri::command]
+async fn set_speed_limit(
+    direction: String,
+    bytes_per_sec: u64,
+    task_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let limits = match direction.as_str() {
+        "download" => &state.limits.download,
+        "upload" => &state.limits.upload,
+        _ => return Err(format!("未知的限速方向: {}", direction)),
     };
+    match task_id {
+        Some(id) => limits.set_task(&id, bytes_per_sec),
+        None => limits.set_global(bytes_per_sec),
+    }
+    info!("{} 限速设置为 {} 字节/秒", direction, bytes_per_sec);
+    Ok(())
+}
 
-    // 步骤 3: 真实下载
-    let res = client
-        .get(&final_download_url)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    // 注意：文件夹打包下载时，API 返回的 Size 可能是 0 或者不准确
-    // 我们优先使用 response header 中的 Content-Length，如果也没有，则无法计算进度
-    let total_size = res
-        .content_length()
-        .unwrap_or(if size > 0 { size as u64 } else { 0 });
-
-    let mut stream = res.bytes_stream();
-    let mut file = File::create(&save_path).map_err(|e| format!("创建文件失败: {}", e))?;
-    let mut downloaded: u64 = 0;
-
-    window
-        .emit(
-            "download-progress",
-            ProgressPayload {
-                id: file_id.to_string(),
-                progress: 0,
-                speed: "".to_string(),
-                status: "downloading".to_string(),
-            },
-        )
-        .unwrap_or(());
-
-    while let Some(item) = stream.next().await {
-        let chunk = item.map_err(|e| format!("下载流中断: {}", e))?;
-        file.write_all(&chunk)
-            .map_err(|e| format!("写入失败: {}", e))?;
-
-        downloaded += chunk.len() as u64;
-
-        if total_size > 0 {
-            let percent = (downloaded * 100) / total_size;
-            window
-                .emit(
-                    "download-progress",
-                    ProgressPayload {
-                        id: file_id.to_string(),
-                        progress: percent,
-                        speed: "".to_string(),
-                        status: "downloading".to_string(),
-                    },
-                )
-                .unwrap_or(());
+// 解析下载地址后推送到 aria2 (JSON-RPC)，返回每个文件的 GID 或错误
+#[tauri::command]
+async fn send_to_aria2(
+    files: Vec<FileInfo>,
+    rpc_url: String,
+    secret: Option<String>,
+    dir: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<aria2::Aria2Result>, String> {
+    let mut results = Vec::new();
+    for info in files {
+        let sent = match aria2::resolve_link(&state, &info, dir.as_deref()).await {
+            Ok(link) => aria2::add_uri(&state.api.client, &rpc_url, secret.as_deref(), &link).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &sent {
+            warn!("推送到 aria2 失败: {} - {}", info.file_name, e);
         }
+        results.push(aria2::Aria2Result {
+            file_id: info.file_id,
+            file_name: info.file_name,
+            gid: sent.as_ref().ok().cloned(),
+            error: sent.err(),
+        });
     }
+    Ok(results)
+}
 
-    info!("文件下载完成: {}", file_name);
-
-    window
-        .emit(
-            "download-progress",
-            ProgressPayload {
-                id: file_id.to_string(),
-                progress: 100,
-                speed: "".to_string(),
-                status: "finished".to_string(),
-            },
-        )
-        .unwrap_or(());
-
-    Ok(())
+// 解析下载地址后导出为 aria2 输入文件，返回写入的链接数
+// 链接有有效期，导出后需要尽快开始下载
+#[tauri::command]
+async fn export_aria2_input(
+    files: Vec<FileInfo>,
+    dir: Option<String>,
+    save_path: String,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    let mut links = Vec::new();
+    for info in &files {
+        match aria2::resolve_link(&state, info, dir.as_deref()).await {
+            Ok(link) => links.push(link),
+            Err(e) => warn!("获取下载地址失败，跳过: {} - {}", info.file_name, e),
+        }
+    }
+    std::fs::write(&save_path, aria2::input_file(&links))
+        .map_err(|e| format!("写入文件失败: {}", e))?;
+    info!("已导出 {} 个 aria2 链接到 {}", links.len(), save_path);
+    Ok(links.len())
 }
 
 async fn calculate_file_md5(file_path: String) -> Result<(String, u64), String> {
@@ -473,237 +594,336 @@ async fn calculate_file_md5(file_path: String) -> Result<(String, u64), String>
 // 上传进度事件
 #[derive(Clone, serde::Serialize)]
 struct UploadProgressPayload {
-    id: String, // path
-    progress: u64,
-    status: String, // "hashing", "uploading", "finished", "error"
+    id: String,     // path
+    status: String, // "hashing", "conflict", "uploading", "finished", "skipped", "error", "cancelled"
+    #[serde(flatten)]
+    snapshot: progress::Snapshot,
+    error: Option<String>, // status 为 "error" 时的原因
 }
 
+// 上传本地文件，存在未完成的上传会话时从断点继续
+// conflict_policy: 同名文件的处理方式 ("ask", "overwrite", "rename", "skip")，默认重命名
+// part_size: 分块大小 (字节)，为空时按文件大小自动选择
 #[tauri::command]
 async fn upload_file(
+    app: tauri::AppHandle,
     parent_file_id: i64,
     file_path: String,
-    window: Window,
-    state: State<'_, AppState>,
-) -> Result<(), String> {
-    let client = &state.client;
-    let token = state.token.lock().unwrap().clone();
-
-    // 获取文件名
-    let path_obj = std::path::Path::new(&file_path);
-    let file_name = path_obj
-        .file_name()
-        .ok_or("无效的文件路径")?
-        .to_str()
-        .ok_or("文件名包含非UTF-8字符")?
-        .to_string();
-
-    // 1. 计算 MD5
-    window
-        .emit(
-            "upload-progress",
-            UploadProgressPayload {
-                id: file_path.clone(),
-                progress: 0,
-                status: "hashing".to_string(),
-            },
-        )
-        .unwrap_or(());
+    conflict_policy: Option<upload::ConflictPolicy>,
+    part_size: Option<u64>,
+) -> Result<upload::UploadOutcome, String> {
+    let options = upload::UploadOptions {
+        policy: conflict_policy.unwrap_or_default(),
+        part_size,
+    };
+    upload::run(&app, parent_file_id, &file_path, options, None).await
+}
 
-    info!("正在计算文件 MD5: {}", file_name);
-    let (etag, size) = calculate_file_md5(file_path.clone()).await?;
+// 把 HTTP 链接指向的文件直接上传到网盘，不经过本地磁盘
+// 没有提供 md5 (或服务器没有报告长度) 时先完整读一遍链接计算 MD5，不能秒传时再请求一次上传
+#[tauri::command]
+async fn upload_from_url(
+    app: tauri::AppHandle,
+    parent_file_id: i64,
+    url: String,
+    file_name: Option<String>,
+    md5: Option<String>,
+) -> Result<upload::UploadOutcome, String> {
+    url_upload::run(&app, parent_file_id, &url, file_name, md5).await
+}
 
-    // 2. 发起上传请求 (Upload Request)
-    let request_url = "https://www.123pan.com/b/api/file/upload_request";
+// 上传整个本地文件夹: 在网盘中重建目录结构，整体进度通过 folder-upload-progress 事件报告
+// include / exclude 为 glob 模式，匹配相对于所选文件夹的路径
+#[tauri::command]
+async fn upload_folder(
+    app: tauri::AppHandle,
+    parent_file_id: i64,
+    local_dir: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    skip_hidden: Option<bool>,
+    conflict_policy: Option<upload::ConflictPolicy>,
+) -> Result<folder_upload::FolderUploadSummary, String> {
+    let filter = folder_upload::UploadFilter::new(
+        &include.unwrap_or_default(),
+        &exclude.unwrap_or_default(),
+        skip_hidden.unwrap_or(true),
+    )?;
+    let policy = conflict_policy.unwrap_or_default();
+    folder_upload::run(&app, parent_file_id, &local_dir, &filter, policy).await
+}
 
-    // 定义一个闭包来构造 Payload，方便重试
-    let create_payload = |dup_policy: i32| {
-        json!({
-            "driveId": 0,
-            "etag": etag,
-            "fileName": file_name,
-            "parentFileId": parent_file_id,
-            "size": size,
-            "type": 0,
-            "duplicate": dup_policy // 0: 询问, 1: 覆盖, 2: 重命名
-        })
-    };
+// 回答 upload-conflict 事件: policy 为 "overwrite"、"rename" 或 "skip"
+#[tauri::command]
+async fn resolve_upload_conflict(
+    conflict_id: String,
+    policy: upload::ConflictPolicy,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.upload_conflicts.answer(&conflict_id, policy)
+}
 
-    // 第一次尝试，默认 duplicate=0
-    let mut req = client.post(request_url).json(&create_payload(0));
-    req = add_auth_headers(req, &token, &state.login_uuid);
+// 在后台预先计算文件夹中所有文件的 MD5，进度通过 prehash-progress 事件报告
+#[tauri::command]
+async fn prehash_directory(app: tauri::AppHandle, dir: String) -> Result<(), String> {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = hash_cache::prehash_dir(&app, &dir).await {
+            error!("预先计算 MD5 失败: {} - {}", dir, e);
+        }
+    });
+    Ok(())
+}
 
-    let res = req.send().await.map_err(|e| e.to_string())?;
-    let mut json_res: UploadRequestResponse = res.json().await.map_err(|e| e.to_string())?;
-
-    // 如果返回 5060 (文件已存在)，自动选择重命名 (duplicate=2) 并重试
-    if json_res.code == 5060 {
-        info!("文件已存在，尝试自动重命名...");
-        let req_retry = client.post(request_url).json(&create_payload(2)); // 2 = Rename
-        let req_retry = add_auth_headers(req_retry, &token, &state.login_uuid);
-        let res_retry = req_retry.send().await.map_err(|e| e.to_string())?;
-        json_res = res_retry.json().await.map_err(|e| e.to_string())?;
-    }
+// 导入秒传 JSON (etag / size / path 列表) 或文本链接，在 parent_file_id 下重建文件
+// 返回秒传成功的路径和失败的原因
+#[tauri::command]
+async fn import_rapid_links(
+    parent_file_id: i64,
+    content: String,
+    state: State<'_, AppState>,
+) -> Result<rapid::RapidImportResult, String> {
+    let entries = rapid::parse(&content)?;
+    info!("开始导入秒传记录: {} 个文件", entries.len());
+    Ok(rapid::import(&state.api, parent_file_id, entries).await)
+}
 
-    if json_res.code != 0 {
-        return Err(format!("上传请求拒绝: {}", json_res.message));
+// 把远程文件夹导出为秒传记录 (format: "json" 或 "text")，返回导出的内容
+// save_path 不为空时同时写入文件
+#[tauri::command]
+async fn export_rapid_links(
+    folder_id: i64,
+    folder_name: String,
+    format: rapid::ExportFormat,
+    save_path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    info!("开始遍历文件夹: {}", folder_name);
+    let mut files = Vec::new();
+    for entry in walk_folder(&state.api, folder_id).await? {
+        if entry.info.file_type == 1 {
+            continue;
+        }
+        match entry.info.etag {
+            Some(etag) if etag.len() == 32 => files.push(rapid::RapidEntry {
+                etag: etag.to_ascii_lowercase(),
+                size: entry.info.size.max(0) as u64,
+                path: entry.path,
+            }),
+            _ => warn!("缺少 Etag，跳过: {}", entry.path),
+        }
     }
 
-    let data = json_res.data.ok_or("API 未返回数据")?;
-
-    // 3. 检查是否秒传
-    if data.reuse {
-        info!("秒传成功: {}", file_name);
-        window
-            .emit(
-                "upload-progress",
-                UploadProgressPayload {
-                    id: file_path.clone(),
-                    progress: 100,
-                    status: "finished".to_string(),
-                },
-            )
-            .unwrap_or(());
-        return Ok(());
+    let content = rapid::export(&format!("{}/", folder_name), &files, format)?;
+    if let Some(path) = save_path {
+        std::fs::write(&path, &content).map_err(|e| format!("写入文件失败: {}", e))?;
     }
+    info!("已导出 {} 个文件的秒传记录", files.len());
+    Ok(content)
+}
 
-    // 4. 准备分块上传 S3
-    let upload_id = data.upload_id.ok_or("缺少 UploadId")?;
-    let key = data.key.ok_or("缺少 Key")?;
-    let bucket = data.bucket.ok_or("缺少 Bucket")?;
-    let storage_node = data.storage_node.unwrap_or_default();
-    let file_id_server = data.file_id; // 最终完成时需要
-
-    // 初始化 S3 上传列表 (Python 源码逻辑)
-    let init_s3_url = "https://www.123pan.com/b/api/file/s3_list_upload_parts";
-    let s3_base_payload = json!({
-        "bucket": bucket,
-        "key": key,
-        "uploadId": upload_id,
-        "storageNode": storage_node
-    });
+// 保存用于转存的其他账号，保存前先确认能登录
+#[tauri::command]
+async fn add_account(
+    app: tauri::AppHandle,
+    username: String,
+    password: String,
+) -> Result<(), String> {
+    accounts::add(&app, &username, &password).await
+}
 
-    let req_init = client.post(init_s3_url).json(&s3_base_payload);
-    let req_init = add_auth_headers(req_init, &token, &state.login_uuid);
-    req_init.send().await.map_err(|e| e.to_string())?;
+#[tauri::command]
+async fn list_accounts(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    Ok(accounts::list(&app))
+}
 
-    // 5. 循环分块上传
-    let block_size: u64 = 5 * 1024 * 1024; // 5MB
-    let mut file = tokio::fs::File::open(&file_path)
-        .await
-        .map_err(|e| e.to_string())?;
-    let mut part_number = 1;
-    let mut uploaded_bytes: u64 = 0;
+#[tauri::command]
+async fn remove_account(app: tauri::AppHandle, username: String) -> Result<(), String> {
+    accounts::remove(&app, &username)
+}
 
-    loop {
-        // 读取 5MB 数据
-        let mut buffer = vec![0u8; block_size as usize];
-        let n = file.read(&mut buffer).await.map_err(|e| e.to_string())?;
+// 把一个账号中的文件夹转存到另一个账号的 dest_folder_id 下，账号为空时使用当前登录的账号
+// 能秒传的直接秒传，否则从源账号下载的同时上传到目标账号
+#[tauri::command]
+async fn transfer_folder(
+    app: tauri::AppHandle,
+    source_account: Option<String>,
+    source_folder_id: i64,
+    source_folder_name: String,
+    dest_account: Option<String>,
+    dest_folder_id: i64,
+    state: State<'_, AppState>,
+) -> Result<transfer::TransferSummary, String> {
+    let source = match source_account {
+        Some(username) => Some(accounts::open(&app, &username).await?),
+        None => None,
+    };
+    let dest = match dest_account {
+        Some(username) => Some(accounts::open(&app, &username).await?),
+        None => None,
+    };
+    transfer::run(
+        &app,
+        source.as_ref().unwrap_or(&state.api),
+        source_folder_id,
+        &source_folder_name,
+        dest.as_ref().unwrap_or(&state.api),
+        dest_folder_id,
+    )
+    .await
+}
 
-        if n == 0 {
-            break;
-        } // EOF
-
-        // 截断 buffer 到实际读取大小 (最后一块可能小于 5MB)
-        buffer.truncate(n);
-
-        // 获取分块上传链接
-        let get_url_api = "https://www.123pan.com/b/api/file/s3_repare_upload_parts_batch";
-        let url_payload = json!({
-            "bucket": bucket,
-            "key": key,
-            "uploadId": upload_id,
-            "storageNode": storage_node,
-            "partNumberStart": part_number,
-            "partNumberEnd": part_number + 1
-        });
+// 取消进行中的上传，id 为 upload-progress 事件中的 ID
+#[tauri::command]
+async fn cancel_upload(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    state.uploads.cancel(&id)
+}
 
-        let req_url = client.post(get_url_api).json(&url_payload);
-        let req_url = add_auth_headers(req_url, &token, &state.login_uuid);
-        let res_url = req_url.send().await.map_err(|e| e.to_string())?;
-        let json_url: PresignedUrlResponse = res_url.json().await.map_err(|e| e.to_string())?;
+// 对比网盘文件夹和本地目录，返回同步计划，不做任何改动
+// delete_extra 为 true 时计划中同时列出网盘中已不存在的本地文件
+#[tauri::command]
+async fn sync_down(
+    app: tauri::AppHandle,
+    remote_folder_id: i64,
+    local_dir: String,
+    delete_extra: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<sync::SyncPlan, String> {
+    let delete_extra = delete_extra.unwrap_or(false);
+    sync::plan(&app, &state, remote_folder_id, &local_dir, delete_extra).await
+}
 
-        if json_url.code != 0 {
-            return Err("获取上传链接失败".to_string());
-        }
+// 执行 sync_down 返回、经用户确认的计划，返回实际执行的部分和下载的文件夹任务 ID
+#[tauri::command]
+async fn apply_sync(
+    app: tauri::AppHandle,
+    plan: sync::SyncPlan,
+    state: State<'_, AppState>,
+) -> Result<sync::SyncPlan, String> {
+    sync::apply(&app, &state, plan).await
+}
 
-        let presigned_url = json_url
-            .data
-            .and_then(|d| d.presigned_urls.get(&part_number.to_string()).cloned())
-            .ok_or("未找到对应分块的上传链接")?;
-
-        // PUT 数据到 S3 (使用不带 Auth Header 的请求)
-        client
-            .put(&presigned_url)
-            .body(buffer) // 直接发送二进制
-            .send()
-            .await
-            .map_err(|e| format!("分块 {} 上传失败: {}", part_number, e))?;
-
-        // 更新进度
-        uploaded_bytes += n as u64;
-        part_number += 1;
-
-        let percent = (uploaded_bytes * 100) / size;
-        window
-            .emit(
-                "upload-progress",
-                UploadProgressPayload {
-                    id: file_path.clone(),
-                    progress: percent,
-                    status: "uploading".to_string(),
-                },
-            )
-            .unwrap_or(());
-    }
+// 列出自动上传的监视文件夹
+#[tauri::command]
+async fn list_watch_folders(app: tauri::AppHandle) -> Result<Vec<watch::WatchFolder>, String> {
+    Ok(watch::load(&app))
+}
 
-    // 6. 完成上传
-    // 发送 S3 完成信号
-    let complete_s3_url = "https://www.123pan.com/b/api/file/s3_complete_multipart_upload";
-    let req_comp_s3 = client.post(complete_s3_url).json(&s3_base_payload);
-    let req_comp_s3 = add_auth_headers(req_comp_s3, &token, &state.login_uuid);
-    req_comp_s3
-        .send()
-        .await
-        .map_err(|e| format!("S3 完成信号发送失败: {}", e))?;
-
-    // 发送业务完成信号
-    let complete_api_url = "https://www.123pan.com/b/api/file/upload_complete";
-    let req_comp_api = client
-        .post(complete_api_url)
-        .json(&json!({ "fileId": file_id_server }));
-    let req_comp_api = add_auth_headers(req_comp_api, &token, &state.login_uuid);
-    let res_final = req_comp_api.send().await.map_err(|e| e.to_string())?;
-
-    // 检查最终结果
-    let status = res_final.status();
-    if !status.is_success() {
-        return Err(format!("服务器返回错误状态: {}", status));
-    }
+// 保存监视文件夹配置并立即生效，文件夹中新增或修改的文件会自动上传
+// 动态通过 watch-activity 事件报告，传空列表时停止监视
+#[tauri::command]
+async fn set_watch_folders(
+    app: tauri::AppHandle,
+    folders: Vec<watch::WatchFolder>,
+) -> Result<(), String> {
+    watch::configure(&app, folders)
+}
 
-    info!("上传流程结束: {}", file_name);
-    window
-        .emit(
-            "upload-progress",
-            UploadProgressPayload {
-                id: file_path.clone(),
-                progress: 100,
-                status: "finished".to_string(),
-            },
-        )
-        .unwrap_or(());
+// 列出未完成的上传 (应用重启后可以继续)
+#[tauri::command]
+async fn list_upload_sessions(app: tauri::AppHandle) -> Result<Vec<upload::UploadSession>, String> {
+    Ok(upload::list_sessions(&app))
+}
 
+// 按保存的会话继续上传到原来的目录
+#[tauri::command]
+async fn resume_upload(
+    app: tauri::AppHandle,
+    file_path: String,
+) -> Result<upload::UploadOutcome, String> {
+    let session = upload::load_session(&app, &file_path).ok_or("没有找到上传记录")?;
+    // 分块大小以会话中保存的为准
+    let options = upload::UploadOptions::default();
+    upload::run(&app, session.parent_file_id, &file_path, options, None).await
+}
+
+#[tauri::command]
+async fn discard_upload_session(app: tauri::AppHandle, file_path: String) -> Result<(), String> {
+    upload::remove_session(&app, &file_path);
     Ok(())
 }
-// 新建文件夹
+
+// 新建文件夹，返回新文件夹的 FileId
 #[tauri::command]
 async fn create_folder(
     parent_file_id: i64,
     folder_name: String,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<i64, String> {
+    create_remote_folder(&state.api, parent_file_id, &folder_name).await
+}
+
+// 相对目录 (以 / 分隔) -> 网盘中的文件夹 ID，缺少的逐级创建，已知的直接复用
+pub struct RemoteFolders {
+    ids: HashMap<String, i64>,
+    // 本次新建的目录，里面不会有已存在的子文件夹，不用再查找
+    created: HashSet<String>,
+}
+
+impl RemoteFolders {
+    // root_id 是已有的文件夹: 子文件夹先查找同名的，没有时再创建
+    pub fn existing(root_id: i64) -> Self {
+        Self {
+            ids: HashMap::from([(String::new(), root_id)]),
+            created: HashSet::new(),
+        }
+    }
+
+    // root_id 是刚创建的空文件夹
+    pub fn created(root_id: i64) -> Self {
+        Self {
+            created: HashSet::from([String::new()]),
+            ..Self::existing(root_id)
+        }
+    }
+
+    pub async fn get_or_create(&mut self, api: &ApiSession, dir: &str) -> Result<i64, String> {
+        if let Some(&id) = self.ids.get(dir) {
+            return Ok(id);
+        }
+        let mut current = String::new();
+        let mut parent_id = self.ids[""];
+        for name in dir.split('/').filter(|n| !n.is_empty()) {
+            let parent_created = self.created.contains(&current);
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(name);
+            if let Some(&id) = self.ids.get(&current) {
+                parent_id = id;
+                continue;
+            }
+
+            let existing = if parent_created {
+                None
+            } else {
+                list_folder(api, parent_id)
+                    .await?
+                    .into_iter()
+                    .find(|f| f.file_type == 1 && f.file_name == name)
+                    .map(|f| f.file_id)
+            };
+            parent_id = match existing {
+                Some(id) => id,
+                None => {
+                    let id = create_remote_folder(api, parent_id, name).await?;
+                    self.created.insert(current.clone());
+                    id
+                }
+            };
+            self.ids.insert(current.clone(), parent_id);
+        }
+        Ok(parent_id)
+    }
+}
+
+async fn create_remote_folder(
+    api: &ApiSession,
+    parent_file_id: i64,
+    folder_name: &str,
+) -> Result<i64, String> {
     info!("尝试创建文件夹: {}", folder_name);
-    let client = &state.client;
-    let token = state.token.lock().unwrap().clone();
+    let client = &api.client;
+    let token = api.token.lock().unwrap().clone();
 
     let url = "https://www.123pan.com/a/api/file/upload_request";
 
@@ -722,7 +942,7 @@ async fn create_folder(
     });
 
     let req = client.post(url).json(&payload);
-    let req = add_auth_headers(req, &token, &state.login_uuid);
+    let req = add_auth_headers(req, &token, &api.login_uuid);
 
     let res = req.send().await.map_err(|e| e.to_string())?;
 
@@ -736,16 +956,23 @@ async fn create_folder(
         return Err(msg);
     }
 
-    info!("创建文件夹成功");
-    Ok(())
+    // 新文件夹的 ID 在 data.Info.FileId 或 data.FileId 中
+    let data = json_res.data.unwrap_or_default();
+    let file_id = data["Info"]["FileId"]
+        .as_i64()
+        .or_else(|| data["FileId"].as_i64())
+        .ok_or("未返回文件夹 ID")?;
+
+    info!("创建文件夹成功: {} ({})", folder_name, file_id);
+    Ok(file_id)
 }
 
 // 6. 删除文件 (新增功能)
 #[tauri::command]
 async fn delete_file(file_id: i64, state: State<'_, AppState>) -> Result<(), String> {
     info!("尝试删除文件 ID: {}", file_id);
-    let client = &state.client;
-    let token = state.token.lock().unwrap().clone();
+    let client = &state.api.client;
+    let token = state.api.token.lock().unwrap().clone();
 
     let url = "https://www.123pan.com/a/api/file/trash";
 
@@ -759,7 +986,7 @@ async fn delete_file(file_id: i64, state: State<'_, AppState>) -> Result<(), Str
     });
 
     let req = client.post(url).json(&payload);
-    let req = add_auth_headers(req, &token, &state.login_uuid);
+    let req = add_auth_headers(req, &token, &state.api.login_uuid);
 
     let res = req.send().await.map_err(|e| e.to_string())?;
     let json_res: ApiResponse<serde_json::Value> = res
@@ -783,8 +1010,8 @@ async fn share_file(
     state: State<'_, AppState>,
 ) -> Result<ShareResult, String> {
     info!("尝试分享文件: {:?}", file_ids);
-    let client = &state.client;
-    let token = state.token.lock().unwrap().clone();
+    let client = &state.api.client;
+    let token = state.api.token.lock().unwrap().clone();
 
     if file_ids.is_empty() {
         return Err("未选择文件".to_string());
@@ -807,7 +1034,7 @@ async fn share_file(
     });
 
     let req = client.post(url).json(&payload);
-    let req = add_auth_headers(req, &token, &state.login_uuid);
+    let req = add_auth_headers(req, &token, &state.api.login_uuid);
     let res = req.send().await.map_err(|e| e.to_string())?;
     let json_res: ShareResponse = res.json().await.map_err(|e| e.to_string())?;
 
@@ -835,15 +1062,51 @@ pub fn run() {
                 .build(),
         )
         .manage(AppState::new())
+        .setup(|app| {
+            // 按保存的配置恢复文件夹监视
+            if let Err(e) = watch::restart(app.handle()) {
+                warn!("恢复文件夹监视失败: {}", e);
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             login,
             get_file_list,
             download_file,
+            download_folder,
+            list_downloads,
+            pause_download,
+            resume_download,
+            cancel_download,
+            clear_downloads,
+            reorder_download,
+            set_max_downloads,
+            set_speed_limit,
+            send_to_aria2,
+            export_aria2_input,
             try_auto_login,
             logout,
             create_folder,
             delete_file,
             upload_file,
+            upload_folder,
+            upload_from_url,
+            resolve_upload_conflict,
+            cancel_upload,
+            sync_down,
+            apply_sync,
+            list_watch_folders,
+            set_watch_folders,
+            prehash_directory,
+            import_rapid_links,
+            export_rapid_links,
+            add_account,
+            list_accounts,
+            remove_account,
+            transfer_folder,
+            list_upload_sessions,
+            resume_upload,
+            discard_upload_session,
             share_file
         ])
         .run(tauri::generate_context!())
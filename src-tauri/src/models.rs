@@ -86,6 +86,7 @@ pub struct UploadRequestData {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PresignedUrlResponse {
     pub code: i32,
+    pub message: Option<String>,
     pub data: Option<PresignedUrlData>,
 }
 
@@ -95,6 +96,35 @@ pub struct PresignedUrlData {
     pub presigned_urls: std::collections::HashMap<String, String>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListPartsResponse {
+    pub code: i32,
+    pub message: Option<String>,
+    pub data: Option<ListPartsData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListPartsData {
+    #[serde(rename = "Parts")]
+    pub parts: Option<Vec<UploadedPart>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadedPart {
+    #[serde(rename = "PartNumber")]
+    pub part_number: serde_json::Value, // 可能是数字或字符串
+    #[serde(rename = "Size")]
+    pub size: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadCompleteData {
+    #[serde(rename = "async", default)]
+    pub is_async: bool, // 服务器仍在后台合并文件
+    #[serde(default)]
+    pub completed: Option<bool>,
+}
+
 // --- 分享相关 ---
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ShareResponse {
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// 两次进度事件之间的最小间隔，避免刷屏
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+// 计算平均速度时参考最近这段时间内的数据
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+// 某一时刻的进度快照，直接展开到进度事件里
+#[derive(Clone, Copy, Serialize, Debug, Default)]
+pub struct Snapshot {
+    pub progress: u64,    // 百分比
+    pub speed: u64,       // 字节/秒
+    pub eta: Option<u64>, // 剩余秒数，速度未知时为空
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+// 下载和上传共用的进度统计: 滑动窗口计算速度和剩余时间，并限制事件频率
+pub struct ProgressTracker {
+    total: u64,
+    done: u64,
+    samples: VecDeque<(Instant, u64)>,
+    last_emit: Option<Instant>,
+}
+
+impl ProgressTracker {
+    pub fn new(total: u64, done: u64) -> Self {
+        let mut samples = VecDeque::new();
+        samples.push_back((Instant::now(), done));
+        Self {
+            total,
+            done,
+            samples,
+            last_emit: None,
+        }
+    }
+
+    pub fn set_total(&mut self, total: u64) {
+        self.total = total;
+    }
+
+    // 已完成的字节数被重置 (例如重新下载) 时清空速度样本
+    pub fn reset(&mut self, done: u64) {
+        self.done = done;
+        self.samples.clear();
+        self.samples.push_back((Instant::now(), done));
+    }
+
+    // 增加已完成字节数，到了发送间隔时返回快照
+    pub fn advance(&mut self, n: u64) -> Option<Snapshot> {
+        self.done += n;
+        let now = Instant::now();
+        self.samples.push_back((now, self.done));
+        while self.samples.len() > 2 && now.duration_since(self.samples[0].0) > SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+
+        match self.last_emit {
+            Some(last) if now.duration_since(last) < EMIT_INTERVAL => None,
+            _ => {
+                self.last_emit = Some(now);
+                Some(self.snapshot())
+            }
+        }
+    }
+
+    // 直接设置已完成字节数 (汇总多个任务时使用)，变小时视为重新开始
+    pub fn set_done(&mut self, done: u64) -> Option<Snapshot> {
+        if done < self.done {
+            self.reset(done);
+            return Some(self.snapshot());
+        }
+        self.advance(done - self.done)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let speed = match (self.samples.front(), self.samples.back()) {
+            (Some((t0, d0)), Some((t1, d1))) => {
+                let secs = t1.duration_since(*t0).as_secs_f64();
+                if secs > 0.0 {
+                    ((d1 - d0) as f64 / secs) as u64
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+        let eta = if speed > 0 && self.total >= self.done {
+            Some((self.total - self.done) / speed)
+        } else {
+            None
+        };
+
+        Snapshot {
+            progress: (self.done * 100)
+                .checked_div(self.total)
+                .unwrap_or(0)
+                .min(100),
+            speed,
+            eta,
+            bytes_done: self.done,
+            bytes_total: self.total,
+        }
+    }
+
+    // 结束时的快照: 进度固定为 100%
+    pub fn finished(&self) -> Snapshot {
+        Snapshot {
+            progress: 100,
+            eta: Some(0),
+            ..self.snapshot()
+        }
+    }
+}
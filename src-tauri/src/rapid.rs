@@ -0,0 +1,385 @@
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::upload::{abort_request, request_upload};
+use crate::{ApiSession, RemoteFolders};
+
+// 秒传链接中 base62 格式的 etag 使用的字符表
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// 文本格式的秒传链接前缀: V1 使用十六进制 etag，V2 使用 base62
+const TEXT_PREFIX_HEX: &str = "123FSLinkV1$";
+const TEXT_PREFIX_BASE62: &str = "123FSLinkV2$";
+
+// 秒传记录中的一个文件，path 为相对路径 (用 / 分隔)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RapidEntry {
+    pub etag: String,
+    #[serde(deserialize_with = "size_from_any")]
+    pub size: u64,
+    pub path: String,
+}
+
+// 常见的秒传 JSON 格式: 带 files 列表的导出文件
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RapidExport {
+    #[serde(default)]
+    pub uses_base62_etags_in_export: bool,
+    #[serde(default)]
+    pub common_path: String,
+    pub files: Vec<RapidEntry>,
+    #[serde(default)]
+    pub total_files_count: usize,
+    #[serde(default)]
+    pub total_size: u64,
+}
+
+// 也接受直接给出的文件列表
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RapidInput {
+    Export(RapidExport),
+    List(Vec<RapidEntry>),
+}
+
+// 有的工具把大小写成字符串
+fn size_from_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => n
+            .as_u64()
+            .ok_or_else(|| serde::de::Error::custom("无效的文件大小")),
+        Value::String(s) => s.trim().parse().map_err(serde::de::Error::custom),
+        _ => Err(serde::de::Error::custom("无效的文件大小")),
+    }
+}
+
+// base62 格式的 etag 还原为 32 位十六进制 MD5
+pub fn base62_to_hex(s: &str) -> Option<String> {
+    let mut n: u128 = 0;
+    for c in s.bytes() {
+        let digit = BASE62.iter().position(|&b| b == c)? as u128;
+        n = n.checked_mul(62)?.checked_add(digit)?;
+    }
+    Some(format!("{:032x}", n))
+}
+
+// 十六进制 MD5 转为 base62 格式
+pub fn hex_to_base62(hex: &str) -> Option<String> {
+    let mut n = u128::from_str_radix(hex, 16).ok()?;
+    let mut digits = Vec::new();
+    while n > 0 {
+        digits.push(BASE62[(n % 62) as usize]);
+        n /= 62;
+    }
+    if digits.is_empty() {
+        digits.push(BASE62[0]);
+    }
+    digits.reverse();
+    String::from_utf8(digits).ok()
+}
+
+// 文本格式: 前缀后跟以 $ 分隔的 "etag#大小#路径"
+fn parse_text(content: &str) -> Option<(bool, Vec<RapidEntry>)> {
+    let content = content.trim();
+    let (base62, body) = if let Some(body) = content.strip_prefix(TEXT_PREFIX_BASE62) {
+        (true, body)
+    } else {
+        (false, content.strip_prefix(TEXT_PREFIX_HEX)?)
+    };
+
+    let mut files = Vec::new();
+    for item in body.split('$').filter(|s| !s.trim().is_empty()) {
+        let mut fields = item.trim().splitn(3, '#');
+        let (etag, size, path) = (fields.next()?, fields.next()?, fields.next()?);
+        files.push(RapidEntry {
+            etag: etag.to_string(),
+            size: size.parse().ok()?,
+            path: path.to_string(),
+        });
+    }
+    Some((base62, files))
+}
+
+// 解析秒传 JSON 或文本链接，返回完整路径的文件列表，etag 统一为十六进制
+pub fn parse(content: &str) -> Result<Vec<RapidEntry>, String> {
+    let parsed = if content.trim_start().starts_with(['{', '[']) {
+        serde_json::from_str::<RapidInput>(content)
+            .map_err(|e| format!("秒传数据格式错误: {}", e))?
+    } else {
+        let (base62, files) = parse_text(content).ok_or("无法识别的秒传链接格式")?;
+        RapidInput::Export(RapidExport {
+            uses_base62_etags_in_export: base62,
+            common_path: String::new(),
+            files,
+            total_files_count: 0,
+            total_size: 0,
+        })
+    };
+    let (base62, common_path, files) = match parsed {
+        RapidInput::Export(export) => (
+            export.uses_base62_etags_in_export,
+            export.common_path,
+            export.files,
+        ),
+        RapidInput::List(files) => (false, String::new(), files),
+    };
+
+    files
+        .into_iter()
+        .map(|mut entry| {
+            // 未声明时按长度判断: 十六进制 MD5 固定 32 位
+            if base62 || entry.etag.len() != 32 {
+                entry.etag = base62_to_hex(&entry.etag)
+                    .ok_or_else(|| format!("无效的 etag: {}", entry.etag))?;
+            }
+            entry.etag = entry.etag.to_ascii_lowercase();
+            entry.path = format!("{}{}", common_path, entry.path)
+                .split(['/', '\\'])
+                .filter(|c| !c.is_empty())
+                .collect::<Vec<_>>()
+                .join("/");
+            if entry.path.is_empty() {
+                return Err("文件路径为空".to_string());
+            }
+            Ok(entry)
+        })
+        .collect()
+}
+
+// 导出格式
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json, // 十六进制 etag，带 commonPath
+    Text, // 123FSLinkV2$ 开头的 base62 链接
+}
+
+// 把文件列表写成秒传记录，path 相对于 common_path
+pub fn export(
+    common_path: &str,
+    files: &[RapidEntry],
+    format: ExportFormat,
+) -> Result<String, String> {
+    match format {
+        ExportFormat::Json => {
+            let manifest = RapidExport {
+                uses_base62_etags_in_export: false,
+                common_path: common_path.to_string(),
+                files: files.to_vec(),
+                total_files_count: files.len(),
+                total_size: files.iter().map(|f| f.size).sum(),
+            };
+            serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())
+        }
+        ExportFormat::Text => {
+            let items = files
+                .iter()
+                .map(|f| {
+                    let etag =
+                        hex_to_base62(&f.etag).ok_or_else(|| format!("无效的 etag: {}", f.path))?;
+                    Ok(format!("{}#{}#{}{}", etag, f.size, common_path, f.path))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(format!("{}{}", TEXT_PREFIX_BASE62, items.join("$")))
+        }
+    }
+}
+
+// 某个条目导入失败的原因
+#[derive(Clone, Serialize, Debug)]
+pub struct RapidFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct RapidImportResult {
+    pub reused: Vec<String>,
+    pub failed: Vec<RapidFailure>,
+}
+
+// 按秒传记录在 parent_file_id 下重建文件: 先建好目录，再逐个发起上传请求
+// 服务器上有相同内容时直接秒传成功，否则记为失败
+pub async fn import(
+    api: &ApiSession,
+    parent_file_id: i64,
+    entries: Vec<RapidEntry>,
+) -> RapidImportResult {
+    let mut result = RapidImportResult::default();
+    let mut folders = RemoteFolders::existing(parent_file_id);
+
+    for entry in entries {
+        let (dir, file_name) = entry.path.rsplit_once('/').unwrap_or(("", &entry.path));
+        let outcome = match folders.get_or_create(api, dir).await {
+            Ok(folder_id) => request_reuse(api, folder_id, file_name, &entry).await,
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(()) => result.reused.push(entry.path),
+            Err(e) => {
+                warn!("秒传失败: {} - {}", entry.path, e);
+                result.failed.push(RapidFailure {
+                    path: entry.path,
+                    error: e,
+                });
+            }
+        }
+    }
+
+    info!(
+        "秒传导入结束: 成功 {}, 失败 {}",
+        result.reused.len(),
+        result.failed.len()
+    );
+    result
+}
+
+async fn request_reuse(
+    api: &ApiSession,
+    parent_file_id: i64,
+    file_name: &str,
+    entry: &RapidEntry,
+) -> Result<(), String> {
+    // 同名时重命名
+    let res = request_upload(api, parent_file_id, file_name, &entry.etag, entry.size, 2).await?;
+    if res.code != 0 {
+        return Err(format!("上传请求拒绝: {}", res.message));
+    }
+    match res.data {
+        Some(data) if data.reuse => Ok(()),
+        Some(data) => {
+            // 没能秒传时服务器已经开启了分块上传，不再使用，通知放弃
+            abort_request(api, &data).await;
+            Err("服务器上没有该文件的内容".to_string())
+        }
+        None => Err("服务器上没有该文件的内容".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+    const LEADING_ZERO_MD5: &str = "00000000000000000000000000000abc";
+
+    fn entry(etag: &str, size: u64, path: &str) -> RapidEntry {
+        RapidEntry {
+            etag: etag.to_string(),
+            size,
+            path: path.to_string(),
+        }
+    }
+
+    fn paths(entries: &[RapidEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.path.as_str()).collect()
+    }
+
+    #[test]
+    fn hex_base62_round_trip() {
+        let zeros = "0".repeat(32);
+        let max = "f".repeat(32);
+        for hex in [EMPTY_MD5, LEADING_ZERO_MD5, zeros.as_str(), max.as_str()] {
+            let base62 = hex_to_base62(hex).unwrap();
+            assert_eq!(base62_to_hex(&base62).as_deref(), Some(hex));
+        }
+    }
+
+    #[test]
+    fn base62_known_values() {
+        assert_eq!(hex_to_base62(&"0".repeat(32)).as_deref(), Some("0"));
+        assert_eq!(
+            hex_to_base62("0000000000000000000000000000003e").as_deref(),
+            Some("10")
+        );
+        assert_eq!(
+            base62_to_hex("z").as_deref(),
+            Some("0000000000000000000000000000003d")
+        );
+        assert_eq!(base62_to_hex("ab-c"), None);
+        assert_eq!(hex_to_base62("not hex"), None);
+    }
+
+    #[test]
+    fn parse_v1_text_link() {
+        let link = format!(
+            "123FSLinkV1${}#0#docs/empty.txt${}#2748#b.bin",
+            EMPTY_MD5.to_ascii_uppercase(),
+            LEADING_ZERO_MD5
+        );
+        let entries = parse(&link).unwrap();
+        assert_eq!(paths(&entries), ["docs/empty.txt", "b.bin"]);
+        assert_eq!(entries[0].etag, EMPTY_MD5);
+        assert_eq!(entries[0].size, 0);
+        assert_eq!(entries[1].etag, LEADING_ZERO_MD5);
+        assert_eq!(entries[1].size, 2748);
+    }
+
+    #[test]
+    fn parse_v2_text_link() {
+        let link = format!(
+            "  123FSLinkV2${}#10#a/b/c.txt${}#20#d.txt\n",
+            hex_to_base62(EMPTY_MD5).unwrap(),
+            hex_to_base62(LEADING_ZERO_MD5).unwrap()
+        );
+        let entries = parse(&link).unwrap();
+        assert_eq!(paths(&entries), ["a/b/c.txt", "d.txt"]);
+        assert_eq!(entries[0].etag, EMPTY_MD5);
+        assert_eq!(entries[1].etag, LEADING_ZERO_MD5);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_text() {
+        assert!(parse("123FSLinkV3$abc#1#a").is_err());
+        assert!(parse(&format!("123FSLinkV1${}#x#a", EMPTY_MD5)).is_err());
+    }
+
+    #[test]
+    fn parse_joins_common_path() {
+        let json = format!(
+            r#"{{"commonPath": "Movies\\2024/", "files": [
+                {{"etag": "{}", "size": 1, "path": "/a//b.mkv"}},
+                {{"etag": "{}", "size": 2, "path": "c.mkv"}}
+            ]}}"#,
+            EMPTY_MD5, LEADING_ZERO_MD5
+        );
+        let entries = parse(&json).unwrap();
+        assert_eq!(
+            paths(&entries),
+            ["Movies/2024/a/b.mkv", "Movies/2024/c.mkv"]
+        );
+    }
+
+    #[test]
+    fn parse_string_size() {
+        let json = format!(
+            r#"[{{"etag": "{}", "size": " 1024 ", "path": "a.txt"}}]"#,
+            EMPTY_MD5
+        );
+        assert_eq!(parse(&json).unwrap()[0].size, 1024);
+
+        let bad = format!(
+            r#"[{{"etag": "{}", "size": "big", "path": "a"}}]"#,
+            EMPTY_MD5
+        );
+        assert!(parse(&bad).is_err());
+    }
+
+    #[test]
+    fn export_then_parse() {
+        let files = [
+            entry(EMPTY_MD5, 0, "empty.txt"),
+            entry(LEADING_ZERO_MD5, 2748, "sub/b.bin"),
+        ];
+        for format in [ExportFormat::Json, ExportFormat::Text] {
+            let content = export("Backup/", &files, format).unwrap();
+            let entries = parse(&content).unwrap();
+            assert_eq!(paths(&entries), ["Backup/empty.txt", "Backup/sub/b.bin"]);
+            for (parsed, original) in entries.iter().zip(&files) {
+                assert_eq!(parsed.etag, original.etag);
+                assert_eq!(parsed.size, original.size);
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use log::debug;
+use regex::Regex;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Client, Url};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 链接里看不出有效期时，缓存这么久
+const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+// 提前这么久视为过期，留出开始下载的时间
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+struct CachedUrl {
+    url: String,
+    expires_at: Instant,
+}
+
+// 把 download_info 返回的中间页地址解析为真实的 CDN 下载地址
+// 复用同一个不跟随跳转的 client，并缓存结果直到签名过期
+pub struct DownloadUrlResolver {
+    client: Client,
+    cache: Mutex<HashMap<String, CachedUrl>>,
+}
+
+impl DownloadUrlResolver {
+    pub fn new() -> Self {
+        let client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .user_agent("123pan/v2.4.0(Android_7.1.2;Xiaomi)")
+            .build()
+            .unwrap();
+
+        Self {
+            client,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 取出未过期的缓存地址
+    pub fn cached(&self, key: &str) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.url.clone()),
+            Some(_) => {
+                cache.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // 地址失效 (例如返回 403) 时丢弃缓存
+    pub fn invalidate(&self, key: &str) {
+        self.cache.lock().unwrap().remove(key);
+    }
+
+    // 请求中间页并解析出真实地址，key 不为空时缓存结果
+    pub async fn resolve(
+        &self,
+        key: Option<&str>,
+        intermediate_url: &str,
+    ) -> Result<String, String> {
+        let res = self
+            .client
+            .get(intermediate_url)
+            .send()
+            .await
+            .map_err(|e| format!("中间页请求失败: {}", e))?;
+
+        let final_url = if let Some(loc) = res.headers().get(LOCATION) {
+            // 检查 Location 头，相对地址按中间页补全
+            let loc = loc.to_str().map_err(|_| "跳转地址无效".to_string())?;
+            Url::parse(intermediate_url)
+                .and_then(|base| base.join(loc))
+                .map(|u| u.to_string())
+                .map_err(|e| format!("跳转地址无效: {}", e))?
+        } else {
+            let content_type = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+
+            // 直接返回了文件内容，中间页本身就是下载地址
+            if res.status().is_success()
+                && !content_type.is_empty()
+                && !content_type.contains("html")
+                && !content_type.contains("json")
+                && !content_type.starts_with("text/")
+            {
+                intermediate_url.to_string()
+            } else {
+                let body = res
+                    .text()
+                    .await
+                    .map_err(|e| format!("读取跳转页失败: {}", e))?;
+                extract_url(&body).ok_or("无法解析下载地址")?
+            }
+        };
+
+        let ttl = url_ttl(&final_url).unwrap_or(DEFAULT_TTL);
+        // 已经 (或即将) 过期的链接不缓存，下次重新获取
+        if let Some(key) = key.filter(|_| !ttl.is_zero()) {
+            debug!("缓存下载地址 {} ({} 秒)", key, ttl.as_secs());
+            self.cache.lock().unwrap().insert(
+                key.to_string(),
+                CachedUrl {
+                    url: final_url.clone(),
+                    expires_at: Instant::now() + ttl,
+                },
+            );
+        }
+        Ok(final_url)
+    }
+}
+
+fn href_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"href\s*=\s*["'](https?://[^"']+)["']"#).unwrap())
+}
+
+fn meta_refresh_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r#"(?i)<meta[^>]*http-equiv\s*=\s*["']?refresh["']?[^>]*content\s*=\s*["'][^"']*?url\s*=\s*['"]?(https?://[^"'\s>]+)"#,
+        )
+        .unwrap()
+    })
+}
+
+// 从中间页内容中提取下载地址，支持 JSON、meta refresh 和 href 链接
+pub fn extract_url(body: &str) -> Option<String> {
+    let trimmed = body.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(trimmed) {
+            return find_json_url(&value);
+        }
+    }
+
+    let caps = meta_refresh_regex()
+        .captures(body)
+        .or_else(|| href_regex().captures(body))?;
+    Some(caps.get(1)?.as_str().replace("&amp;", "&"))
+}
+
+// 在 JSON 中查找地址: 优先找键名里带 url 的字段，再找任意 http 链接
+fn find_json_url(value: &serde_json::Value) -> Option<String> {
+    fn walk(value: &serde_json::Value, keyed_only: bool) -> Option<String> {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, v) in map {
+                    if let Some(s) = v.as_str() {
+                        let named = key.to_ascii_lowercase().contains("url");
+                        if is_http(s) && (named || !keyed_only) {
+                            return Some(s.to_string());
+                        }
+                    }
+                }
+                map.values().find_map(|v| walk(v, keyed_only))
+            }
+            serde_json::Value::Array(items) => items.iter().find_map(|v| walk(v, keyed_only)),
+            serde_json::Value::String(s) if !keyed_only && is_http(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+    walk(value, true).or_else(|| walk(value, false))
+}
+
+fn is_http(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+// 根据签名参数推算链接剩余有效期，无法推算时为空，已过期时为 0
+fn url_ttl(url: &str) -> Option<Duration> {
+    let url = Url::parse(url).ok()?;
+    let params: HashMap<String, String> = url
+        .query_pairs()
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.into_owned()))
+        .collect();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let expires_at = if let Some(ts) = params.get("expires").and_then(|v| v.parse::<u64>().ok()) {
+        // S3 v2 / OSS: Expires=过期时间戳
+        ts
+    } else if let (Some(date), Some(secs)) = (params.get("x-amz-date"), params.get("x-amz-expires"))
+    {
+        // S3 v4: X-Amz-Date=20240101T000000Z&X-Amz-Expires=秒数
+        amz_date_to_unix(date)? + secs.parse::<u64>().ok()?
+    } else if let Some(auth_key) = params.get("auth_key") {
+        // CDN 鉴权: auth_key=时间戳-随机数-uid-签名
+        auth_key.split('-').next()?.parse::<u64>().ok()?
+    } else {
+        return None;
+    };
+
+    let remaining = expires_at.saturating_sub(now);
+    Some(Duration::from_secs(remaining).saturating_sub(EXPIRY_MARGIN))
+}
+
+// 20240101T000000Z -> Unix 时间戳
+fn amz_date_to_unix(date: &str) -> Option<u64> {
+    if date.len() != 16 {
+        return None;
+    }
+    let num = |range: std::ops::Range<usize>| date.get(range)?.parse::<i64>().ok();
+    let (y, m, d) = (num(0..4)?, num(4..6)?, num(6..8)?);
+    let (hh, mm, ss) = (num(9..11)?, num(11..13)?, num(13..15)?);
+
+    // 公历日期转天数 (Howard Hinnant 的 days_from_civil 算法)
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    u64::try_from(days * 86400 + hh * 3600 + mm * 60 + ss).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, serve};
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    // 实际剩余时间与期望值相差不超过几秒 (测试运行期间时间在走)
+    fn assert_ttl(url: &str, expires_at: u64) {
+        let ttl = url_ttl(url).unwrap().as_secs();
+        let expected = expires_at - now() - EXPIRY_MARGIN.as_secs();
+        assert!(ttl.abs_diff(expected) <= 2, "{} != {}", ttl, expected);
+    }
+
+    #[tokio::test]
+    async fn resolve_follows_relative_location() {
+        let redirect = response("302 Found", &[("Location", "/file/a.zip?x=1")], "");
+        let (base, server) = serve(vec![redirect]).await;
+        let resolver = DownloadUrlResolver::new();
+
+        let url = resolver
+            .resolve(Some("k"), &format!("{}/redirect", base))
+            .await
+            .unwrap();
+        assert_eq!(url, format!("{}/file/a.zip?x=1", base));
+        assert_eq!(resolver.cached("k"), Some(url));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolve_does_not_cache_expired_url() {
+        let redirect = response("302 Found", &[("Location", "/a.zip?Expires=1000")], "");
+        let (base, server) = serve(vec![redirect]).await;
+        let resolver = DownloadUrlResolver::new();
+
+        resolver
+            .resolve(Some("k"), &format!("{}/redirect", base))
+            .await
+            .unwrap();
+        assert_eq!(resolver.cached("k"), None);
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn extract_href_single_quotes() {
+        let body = "<a class='btn' href='https://cdn.example.com/a.zip?sign=1'>下载</a>";
+        assert_eq!(
+            extract_url(body).as_deref(),
+            Some("https://cdn.example.com/a.zip?sign=1")
+        );
+    }
+
+    #[test]
+    fn extract_href_double_quotes() {
+        let body = r#"<html><body><a href = "http://cdn.example.com/b.bin">go</a></body></html>"#;
+        assert_eq!(
+            extract_url(body).as_deref(),
+            Some("http://cdn.example.com/b.bin")
+        );
+    }
+
+    #[test]
+    fn extract_meta_refresh_unescapes_amp() {
+        let body = r#"<head><META HTTP-EQUIV="Refresh" CONTENT="0; URL=https://cdn.example.com/c.zip?a=1&amp;b=2"></head>
+            <a href="https://example.com/other">other</a>"#;
+        assert_eq!(
+            extract_url(body).as_deref(),
+            Some("https://cdn.example.com/c.zip?a=1&b=2")
+        );
+    }
+
+    #[test]
+    fn extract_nested_json_url_key() {
+        let body = r#"{"code":0,"message":"https://example.com/help","data":{"list":[{"DownloadUrl":"https://cdn.example.com/d.zip"}]}}"#;
+        assert_eq!(
+            extract_url(body).as_deref(),
+            Some("https://cdn.example.com/d.zip")
+        );
+    }
+
+    #[test]
+    fn extract_json_without_url_key_falls_back_to_any_link() {
+        let body = r#"{"data":["https://cdn.example.com/e.zip"]}"#;
+        assert_eq!(
+            extract_url(body).as_deref(),
+            Some("https://cdn.example.com/e.zip")
+        );
+    }
+
+    #[test]
+    fn extract_unparseable_page() {
+        assert_eq!(extract_url("<html><body>文件不存在</body></html>"), None);
+        assert_eq!(extract_url(r#"{"code":404,"message":"not found"}"#), None);
+    }
+
+    #[test]
+    fn ttl_from_expires() {
+        let expires_at = now() + 3600;
+        assert_ttl(
+            &format!("https://cdn.example.com/a?Expires={}&sign=x", expires_at),
+            expires_at,
+        );
+    }
+
+    #[test]
+    fn ttl_from_amz_date_and_expires() {
+        let date = "20991231T235959Z";
+        let expires_at = amz_date_to_unix(date).unwrap() + 600;
+        assert_ttl(
+            &format!(
+                "https://s3.example.com/a?X-Amz-Date={}&X-Amz-Expires=600",
+                date
+            ),
+            expires_at,
+        );
+    }
+
+    #[test]
+    fn ttl_from_auth_key() {
+        let expires_at = now() + 1800;
+        assert_ttl(
+            &format!(
+                "https://cdn.example.com/a?auth_key={}-abc-0-0123456789abcdef",
+                expires_at
+            ),
+            expires_at,
+        );
+    }
+
+    #[test]
+    fn ttl_unknown() {
+        assert_eq!(url_ttl("https://cdn.example.com/a?sign=x"), None);
+        assert_eq!(url_ttl("not a url"), None);
+    }
+
+    #[test]
+    fn ttl_expired() {
+        assert_eq!(
+            url_ttl("https://cdn.example.com/a?Expires=1000"),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn amz_date_conversion() {
+        assert_eq!(amz_date_to_unix("19700101T000000Z"), Some(0));
+        assert_eq!(amz_date_to_unix("20240101T000000Z"), Some(1704067200));
+        assert_eq!(amz_date_to_unix("20240229T123456Z"), Some(1709210096));
+        assert_eq!(amz_date_to_unix("2024-01-01"), None);
+    }
+}
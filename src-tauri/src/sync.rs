@@ -0,0 +1,207 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tauri::AppHandle;
+use walkdir::WalkDir;
+
+use crate::download::{self, local_path, DownloadJob};
+use crate::hash_cache::{self, file_stamp};
+use crate::models::FileInfo;
+use crate::AppState;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncReason {
+    New,     // 本地没有
+    Changed, // 大小或 MD5 不同
+}
+
+// 需要下载的一个文件，path 为相对路径 (用 / 分隔)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyncItem {
+    pub path: String,
+    pub size: i64,
+    pub reason: SyncReason,
+    info: FileInfo, // 执行时用来创建下载任务
+}
+
+// 需要删除的一个本地文件，记下对比时的大小和修改时间
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyncDelete {
+    pub path: String,
+    pub size: u64,
+    modified: SystemTime,
+}
+
+// 同步计划: 先由 plan 生成交给用户确认，确认后原样交给 apply 执行
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SyncPlan {
+    pub remote_folder_id: i64,
+    pub local_dir: String,
+    pub download: Vec<SyncItem>,
+    pub download_bytes: u64,
+    pub delete: Vec<SyncDelete>, // 网盘中已不存在的本地文件
+    pub unchanged: usize,
+    pub group_id: Option<String>, // 执行后下载任务所属的文件夹任务
+}
+
+// 本地文件与网盘文件是否相同: 先比大小，再用 Etag 比 MD5；本地没有该文件时为 None
+async fn compare(app: &AppHandle, local: &Path, info: &FileInfo) -> Result<Option<bool>, String> {
+    let Ok(meta) = std::fs::metadata(local) else {
+        return Ok(None);
+    };
+    if !meta.is_file() || meta.len() != info.size.max(0) as u64 {
+        return Ok(Some(false));
+    }
+    match info.etag.as_deref().filter(|e| e.len() == 32) {
+        Some(etag) => {
+            let (md5, _) = hash_cache::file_md5(app, &local.to_string_lossy()).await?;
+            Ok(Some(md5.eq_ignore_ascii_case(etag)))
+        }
+        // 没有 Etag 时只能比较大小
+        None => Ok(Some(true)),
+    }
+}
+
+// 本地目录中的文件 (相对路径)，不包括下载中的临时文件
+fn local_files(root: &Path) -> Vec<String> {
+    WalkDir::new(root)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let relative = e
+                .path()
+                .strip_prefix(root)
+                .ok()?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let temp = relative.ends_with(".part") || relative.ends_with(".part.json");
+            (!temp).then_some(relative)
+        })
+        .collect()
+}
+
+// 对比网盘文件夹和本地目录，列出需要下载和 (delete_extra 时) 需要删除的文件，不做任何改动
+pub async fn plan(
+    app: &AppHandle,
+    state: &AppState,
+    remote_folder_id: i64,
+    local_dir: &str,
+    delete_extra: bool,
+) -> Result<SyncPlan, String> {
+    let root = Path::new(local_dir);
+    let mut plan = SyncPlan {
+        remote_folder_id,
+        local_dir: local_dir.to_string(),
+        ..SyncPlan::default()
+    };
+    let mut remote_files = HashSet::new();
+
+    info!("开始对比: {} <- {}", local_dir, remote_folder_id);
+    for entry in crate::walk_folder(&state.api, remote_folder_id).await? {
+        if entry.info.file_type == 1 {
+            continue;
+        }
+        remote_files.insert(entry.path.clone());
+        let local = local_path(root, &entry.path)?;
+        let reason = match compare(app, &local, &entry.info).await? {
+            None => SyncReason::New,
+            Some(false) => SyncReason::Changed,
+            Some(true) => {
+                plan.unchanged += 1;
+                continue;
+            }
+        };
+        plan.download_bytes += entry.info.size.max(0) as u64;
+        plan.download.push(SyncItem {
+            path: entry.path,
+            size: entry.info.size,
+            reason,
+            info: entry.info,
+        });
+    }
+
+    if delete_extra && root.is_dir() {
+        for path in local_files(root) {
+            if remote_files.contains(&path) {
+                continue;
+            }
+            let (size, modified) = file_stamp(&local_path(root, &path)?.to_string_lossy())?;
+            plan.delete.push(SyncDelete {
+                path,
+                size,
+                modified,
+            });
+        }
+    }
+    info!(
+        "对比完成: 下载 {} 个, 删除 {} 个, 未变 {} 个",
+        plan.download.len(),
+        plan.delete.len(),
+        plan.unchanged
+    );
+    Ok(plan)
+}
+
+// 执行用户确认过的计划: 删除本地多出的文件，并把需要下载的文件作为一个文件夹任务加入下载队列
+// 确认之后本地文件可能又有变化，每一项执行前重新检查: 删除的文件必须和对比时一样，
+// 已经和网盘相同的文件不再下载。下载完成后才替换已有的文件
+pub async fn apply(
+    app: &AppHandle,
+    state: &AppState,
+    mut plan: SyncPlan,
+) -> Result<SyncPlan, String> {
+    let root = PathBuf::from(&plan.local_dir);
+    let mut deleted = Vec::new();
+    for item in plan.delete {
+        let local = local_path(&root, &item.path)?;
+        match file_stamp(&local.to_string_lossy()) {
+            Ok(stamp) if stamp == (item.size, item.modified) => {}
+            _ => {
+                warn!("本地文件在确认后有变化，不删除: {}", item.path);
+                continue;
+            }
+        }
+        match std::fs::remove_file(&local) {
+            Ok(()) => {
+                info!("已删除本地文件: {}", item.path);
+                deleted.push(item);
+            }
+            Err(e) => warn!("删除本地文件失败: {} - {}", item.path, e),
+        }
+    }
+    plan.delete = deleted;
+
+    let mut jobs = Vec::new();
+    let mut download = Vec::new();
+    for item in plan.download {
+        let local = local_path(&root, &item.path)?;
+        if compare(app, &local, &item.info).await? == Some(true) {
+            info!("本地文件已和网盘相同，不再下载: {}", item.path);
+            continue;
+        }
+        if let Some(parent) = local.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("创建目录失败: {}", e))?;
+        }
+        let mut job = DownloadJob::for_file(&item.info);
+        job.save_path = local.to_string_lossy().to_string();
+        job.max_retries = download::DEFAULT_MAX_RETRIES;
+        jobs.push(job);
+        download.push(item);
+    }
+    plan.download_bytes = download.iter().map(|i| i.size.max(0) as u64).sum();
+    plan.download = download;
+    plan.group_id = Some(
+        state
+            .downloads
+            .enqueue_folder(app, plan.remote_folder_id, jobs),
+    );
+    Ok(plan)
+}
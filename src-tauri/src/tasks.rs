@@ -0,0 +1,414 @@
+use log::{error, info};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use uuid::Uuid;
+
+use crate::download::{self, DownloadJob};
+use crate::progress::{ProgressTracker, Snapshot};
+use crate::AppState;
+
+const DEFAULT_MAX_ACTIVE: usize = 3;
+
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Paused,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+// 返回给前端的任务信息
+#[derive(Clone, Serialize, Debug)]
+pub struct TaskInfo {
+    pub id: String,
+    pub file_id: i64,
+    pub file_name: String,
+    pub save_path: String,
+    pub group: Option<String>,
+    pub status: TaskStatus,
+    pub error: Option<String>,
+}
+
+struct Task {
+    job: DownloadJob,
+    status: TaskStatus,
+    error: Option<String>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Task {
+    fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.job.id.clone(),
+            file_id: self.job.file_id,
+            file_name: self.job.file_name.clone(),
+            save_path: self.job.save_path.clone(),
+            group: self.job.group.clone(),
+            status: self.status,
+            error: self.error.clone(),
+        }
+    }
+}
+
+struct Queue {
+    max_active: usize,
+    // 按优先级排序，越靠前越先开始
+    tasks: Vec<Task>,
+}
+
+impl Queue {
+    fn find(&mut self, id: &str) -> Result<&mut Task, String> {
+        self.tasks
+            .iter_mut()
+            .find(|t| t.job.id == id)
+            .ok_or_else(|| format!("任务不存在: {}", id))
+    }
+}
+
+// 文件夹下载的整体进度事件
+#[derive(Clone, Serialize)]
+struct FolderProgressPayload {
+    id: String, // 文件夹任务 ID
+    folder_id: i64,
+    files_total: usize,
+    files_done: usize,
+    files_failed: usize,
+    status: String, // "downloading", "finished", "error"
+    #[serde(flatten)]
+    snapshot: Snapshot,
+}
+
+// 文件夹下载: 由多个单文件任务组成，汇总它们的进度
+struct FolderGroup {
+    folder_id: i64,
+    files_total: usize,
+    bytes: HashMap<String, u64>, // 任务 ID -> 已下载字节数
+    finished: HashSet<String>,
+    failed: HashSet<String>,
+    tracker: ProgressTracker,
+}
+
+impl FolderGroup {
+    fn payload(&self, id: &str, snapshot: Snapshot) -> FolderProgressPayload {
+        let status = if self.finished.len() + self.failed.len() < self.files_total {
+            "downloading"
+        } else if self.failed.is_empty() {
+            "finished"
+        } else {
+            "error"
+        };
+        FolderProgressPayload {
+            id: id.to_string(),
+            folder_id: self.folder_id,
+            files_total: self.files_total,
+            files_done: self.finished.len(),
+            files_failed: self.failed.len(),
+            status: status.to_string(),
+            snapshot,
+        }
+    }
+}
+
+// 下载队列: 同时最多运行 max_active 个任务，其余排队
+pub struct DownloadManager {
+    queue: Mutex<Queue>,
+    groups: Mutex<HashMap<String, FolderGroup>>,
+    // 任务 ID -> 最近一次的进度，暂停、取消、失败时沿用
+    progress: Mutex<HashMap<String, Snapshot>>,
+}
+
+impl DownloadManager {
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(Queue {
+                max_active: DEFAULT_MAX_ACTIVE,
+                tasks: Vec::new(),
+            }),
+            groups: Mutex::new(HashMap::new()),
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 记录任务最近一次的进度 (不能锁队列，发送进度时队列可能已被锁住)
+    pub fn record(&self, id: &str, snapshot: Snapshot) {
+        self.progress
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), snapshot);
+    }
+
+    // 任务停下时的进度: 保留已下载的字节数，速度和剩余时间清零
+    fn last_snapshot(&self, id: &str) -> Snapshot {
+        let progress = self.progress.lock().unwrap();
+        let snapshot = progress.get(id).copied().unwrap_or_default();
+        Snapshot {
+            speed: 0,
+            eta: None,
+            ..snapshot
+        }
+    }
+
+    // 把文件夹中的所有文件作为一组加入队列，返回文件夹任务 ID
+    pub fn enqueue_folder(
+        &self,
+        app: &AppHandle,
+        folder_id: i64,
+        mut jobs: Vec<DownloadJob>,
+    ) -> String {
+        let group_id = Uuid::new_v4().simple().to_string();
+        let total: u64 = jobs.iter().map(|j| j.size.max(0) as u64).sum();
+        let group = FolderGroup {
+            folder_id,
+            files_total: jobs.len(),
+            bytes: HashMap::new(),
+            finished: HashSet::new(),
+            failed: HashSet::new(),
+            tracker: ProgressTracker::new(total, 0),
+        };
+        app.emit(
+            "folder-progress",
+            group.payload(&group_id, group.tracker.snapshot()),
+        )
+        .unwrap_or(());
+        if jobs.is_empty() {
+            return group_id;
+        }
+        self.groups.lock().unwrap().insert(group_id.clone(), group);
+
+        for job in jobs.iter_mut() {
+            job.group = Some(group_id.clone());
+        }
+        for job in jobs {
+            self.enqueue(app, job);
+        }
+        group_id
+    }
+
+    // 单个文件的进度变化时更新所属文件夹的进度
+    pub fn update_group(
+        &self,
+        app: &AppHandle,
+        job: &DownloadJob,
+        snapshot: &Snapshot,
+        status: &str,
+    ) {
+        let Some(group_id) = &job.group else {
+            return;
+        };
+        let mut groups = self.groups.lock().unwrap();
+        let Some(group) = groups.get_mut(group_id) else {
+            return;
+        };
+
+        let mut force = false;
+        match status {
+            "finished" => {
+                group.failed.remove(&job.id);
+                force = group.finished.insert(job.id.clone());
+            }
+            "error" => force = group.failed.insert(job.id.clone()),
+            "cancelled" => {
+                group.bytes.remove(&job.id);
+                force = group.failed.insert(job.id.clone());
+            }
+            _ => {}
+        }
+        if status != "cancelled" && snapshot.bytes_done > 0 {
+            group.bytes.insert(job.id.clone(), snapshot.bytes_done);
+        }
+
+        let done = group.bytes.values().sum();
+        let snapshot = match group.tracker.set_done(done) {
+            Some(snapshot) => snapshot,
+            None if force => group.tracker.snapshot(),
+            None => return,
+        };
+        let payload = group.payload(group_id, snapshot);
+        if payload.status != "downloading" {
+            groups.remove(group_id);
+        }
+        app.emit("folder-progress", payload).unwrap_or(());
+    }
+
+    // 加入队列，返回新任务的 ID
+    pub fn enqueue(&self, app: &AppHandle, mut job: DownloadJob) -> String {
+        job.id = Uuid::new_v4().simple().to_string();
+        let id = job.id.clone();
+        info!("加入下载队列: {} ({})", job.file_name, id);
+
+        download::emit_progress(app, &job, Snapshot::default(), "queued");
+        self.queue.lock().unwrap().tasks.push(Task {
+            job,
+            status: TaskStatus::Queued,
+            error: None,
+            handle: None,
+        });
+        self.schedule(app);
+        id
+    }
+
+    pub fn list(&self) -> Vec<TaskInfo> {
+        let queue = self.queue.lock().unwrap();
+        queue.tasks.iter().map(|t| t.info()).collect()
+    }
+
+    // 暂停: 中止正在运行的任务，已写入的数据保留在 .part 中，恢复时续传
+    pub fn pause(&self, app: &AppHandle, id: &str) -> Result<(), String> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let task = queue.find(id)?;
+            match task.status {
+                TaskStatus::Running | TaskStatus::Queued => {
+                    if let Some(handle) = task.handle.take() {
+                        handle.abort();
+                    }
+                    task.status = TaskStatus::Paused;
+                    let snapshot = self.last_snapshot(id);
+                    download::emit_progress(app, &task.job, snapshot, "paused");
+                }
+                _ => return Err("任务当前无法暂停".to_string()),
+            }
+        }
+        self.schedule(app);
+        Ok(())
+    }
+
+    // 恢复: 暂停或失败的任务重新排队
+    pub fn resume(&self, app: &AppHandle, id: &str) -> Result<(), String> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let task = queue.find(id)?;
+            match task.status {
+                TaskStatus::Paused | TaskStatus::Failed => {
+                    task.status = TaskStatus::Queued;
+                    task.error = None;
+                    let snapshot = self.last_snapshot(id);
+                    download::emit_progress(app, &task.job, snapshot, "queued");
+                }
+                _ => return Err("任务当前无法恢复".to_string()),
+            }
+        }
+        self.schedule(app);
+        Ok(())
+    }
+
+    // 取消: 中止任务并删除临时文件
+    pub fn cancel(&self, app: &AppHandle, id: &str) -> Result<(), String> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let task = queue.find(id)?;
+            if matches!(task.status, TaskStatus::Finished | TaskStatus::Cancelled) {
+                return Err("任务已结束".to_string());
+            }
+            if let Some(handle) = task.handle.take() {
+                handle.abort();
+            }
+            task.status = TaskStatus::Cancelled;
+            download::discard_part(&task.job.save_path);
+            let snapshot = self.last_snapshot(id);
+            download::emit_progress(app, &task.job, snapshot, "cancelled");
+            app.state::<AppState>().limits.download.release(id);
+        }
+        self.schedule(app);
+        Ok(())
+    }
+
+    // 从列表中移除已完成和已取消的任务，返回移除的数量
+    pub fn clear_finished(&self) -> usize {
+        let mut queue = self.queue.lock().unwrap();
+        let mut progress = self.progress.lock().unwrap();
+        let before = queue.tasks.len();
+        queue.tasks.retain(|t| {
+            let ended = matches!(t.status, TaskStatus::Finished | TaskStatus::Cancelled);
+            if ended {
+                progress.remove(&t.job.id);
+            }
+            !ended
+        });
+        before - queue.tasks.len()
+    }
+
+    // 调整优先级: 把任务移动到队列中的 position 位置
+    pub fn reorder(&self, id: &str, position: usize) -> Result<(), String> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = queue
+            .tasks
+            .iter()
+            .position(|t| t.job.id == id)
+            .ok_or_else(|| format!("任务不存在: {}", id))?;
+        let task = queue.tasks.remove(index);
+        let position = position.min(queue.tasks.len());
+        queue.tasks.insert(position, task);
+        Ok(())
+    }
+
+    pub fn set_max_active(&self, app: &AppHandle, max_active: usize) {
+        self.queue.lock().unwrap().max_active = max_active.max(1);
+        self.schedule(app);
+    }
+
+    // 按队列顺序启动排队中的任务，直到达到并发上限
+    fn schedule(&self, app: &AppHandle) {
+        let mut queue = self.queue.lock().unwrap();
+        let max_active = queue.max_active;
+        let mut running = queue
+            .tasks
+            .iter()
+            .filter(|t| t.status == TaskStatus::Running)
+            .count();
+
+        for task in queue.tasks.iter_mut() {
+            if running >= max_active {
+                break;
+            }
+            if task.status != TaskStatus::Queued {
+                continue;
+            }
+            task.status = TaskStatus::Running;
+            running += 1;
+
+            let app = app.clone();
+            let job = task.job.clone();
+            task.handle = Some(tauri::async_runtime::spawn(async move {
+                let result = download::run(&app, &job).await;
+                let state = app.state::<AppState>();
+                state.downloads.finish(&app, &job.id, result);
+            }));
+        }
+    }
+
+    // 任务结束 (成功或失败) 后更新状态并启动下一个
+    fn finish(&self, app: &AppHandle, id: &str, result: Result<(), String>) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let Ok(task) = queue.find(id) else {
+                return;
+            };
+            // 已被暂停或取消的任务不再更新
+            if task.status != TaskStatus::Running {
+                return;
+            }
+            task.handle = None;
+            match result {
+                Ok(()) => {
+                    task.status = TaskStatus::Finished;
+                    app.state::<AppState>().limits.download.release(id);
+                }
+                Err(e) => {
+                    error!("下载失败: {} - {}", task.job.file_name, e);
+                    let snapshot = self.last_snapshot(id);
+                    download::emit_error(app, &task.job, snapshot, &e);
+                    task.status = TaskStatus::Failed;
+                    task.error = Some(e);
+                }
+            }
+        }
+        self.schedule(app);
+    }
+}
@@ -0,0 +1,63 @@
+// 测试用的本地 HTTP 服务: 每个连接按顺序返回一个写好的响应，并记下收到的请求
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+// 组装一个完整的 HTTP 响应，响应后关闭连接
+pub fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut raw = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    raw
+}
+
+// 启动服务，返回地址 (http://127.0.0.1:端口) 和收到的请求 (请求头 + 请求体)
+pub async fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let handle = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            requests.push(read_request(&mut stream).await);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap_or(());
+        }
+        requests
+    });
+    (base, handle)
+}
+
+async fn read_request(stream: &mut TcpStream) -> String {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&data);
+        let Some(end) = text.find("\r\n\r\n") else {
+            continue;
+        };
+        let length = text[..end]
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+        if data.len() >= end + 4 + length {
+            break;
+        }
+    }
+    String::from_utf8_lossy(&data).into_owned()
+}
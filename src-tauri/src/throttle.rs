@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 上传时把分块拆成这么大的小块，逐块申请令牌
+const UPLOAD_CHUNK: usize = 64 * 1024;
+
+// 令牌桶: 每秒补充 rate 个字节的令牌，最多积攒一秒的量
+// 令牌不足时允许透支，由调用方等待透支部分补回来
+struct TokenBucket {
+    rate: u64, // 字节/秒，0 表示不限速
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+    }
+
+    // 取走 n 个令牌，返回需要等待的时间
+    fn take(&mut self, n: u64) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill();
+        self.tokens -= n as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+// 可在多个任务间共享的限速器，运行中可以修改速率
+#[derive(Clone)]
+pub struct RateLimiter(Arc<Mutex<TokenBucket>>);
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self(Arc::new(Mutex::new(TokenBucket {
+            rate,
+            tokens: rate as f64,
+            last: Instant::now(),
+        })))
+    }
+
+    pub fn set_rate(&self, rate: u64) {
+        let mut bucket = self.0.lock().unwrap();
+        bucket.refill();
+        bucket.rate = rate;
+        // 新速率下透支的部分不再追究，多余的令牌也不保留
+        bucket.tokens = bucket.tokens.clamp(0.0, rate as f64);
+    }
+
+    pub async fn acquire(&self, n: u64) {
+        let wait = self.0.lock().unwrap().take(n);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// 一个方向 (上传或下载) 的限速: 全局限速加上每个任务各自的限速
+pub struct DirectionLimits {
+    global: RateLimiter,
+    tasks: Mutex<HashMap<String, RateLimiter>>,
+}
+
+impl DirectionLimits {
+    fn new() -> Self {
+        Self {
+            global: RateLimiter::new(0),
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_global(&self, rate: u64) {
+        self.global.set_rate(rate);
+    }
+
+    // 任务还没开始时也可以先设置，开始后沿用同一个限速器
+    pub fn set_task(&self, id: &str, rate: u64) {
+        self.task(id).set_rate(rate);
+    }
+
+    fn task(&self, id: &str) -> RateLimiter {
+        self.tasks
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_insert_with(|| RateLimiter::new(0))
+            .clone()
+    }
+
+    // 取得某个任务的限速器组合，传输时每读写一块数据调用一次 consume
+    pub fn for_task(&self, id: &str) -> Throttle {
+        Throttle {
+            global: self.global.clone(),
+            task: self.task(id),
+        }
+    }
+
+    // 任务结束后不再需要单独的限速设置
+    pub fn release(&self, id: &str) {
+        self.tasks.lock().unwrap().remove(id);
+    }
+}
+
+// 上传和下载分别限速
+pub struct BandwidthLimits {
+    pub download: DirectionLimits,
+    pub upload: DirectionLimits,
+}
+
+impl BandwidthLimits {
+    pub fn new() -> Self {
+        Self {
+            download: DirectionLimits::new(),
+            upload: DirectionLimits::new(),
+        }
+    }
+}
+
+// 单个任务实际使用的限速: 同时受全局和任务自身的限制
+#[derive(Clone)]
+pub struct Throttle {
+    global: RateLimiter,
+    task: RateLimiter,
+}
+
+impl Throttle {
+    pub async fn consume(&self, n: u64) {
+        self.global.acquire(n).await;
+        self.task.acquire(n).await;
+    }
+
+    // 把分块数据包装成限速的请求体，按小块发送，发送完后释放 data
+    // 流式请求体没有长度，调用方需要自行设置 Content-Length
+    pub fn body<T: AsRef<[u8]> + Send + 'static>(&self, data: T) -> reqwest::Body {
+        let throttle = self.clone();
+        let stream = futures_util::stream::unfold(0usize, move |offset| {
+            let throttle = throttle.clone();
+            let data = data.as_ref();
+            let end = (offset + UPLOAD_CHUNK).min(data.len());
+            let chunk = data[offset..end].to_vec();
+            async move {
+                if chunk.is_empty() {
+                    return None;
+                }
+                throttle.consume(chunk.len() as u64).await;
+                Some((Ok::<_, std::io::Error>(chunk), end))
+            }
+        });
+        reqwest::Body::wrap_stream(stream)
+    }
+}
@@ -0,0 +1,169 @@
+use futures_util::Stream;
+use log::{info, warn};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::download::{self, DownloadJob};
+use crate::models::FileInfo;
+use crate::resolver::DownloadUrlResolver;
+use crate::upload::{self, StreamTarget, UploadOutcome};
+use crate::{ApiSession, AppState, RemoteEntry, RemoteFolders};
+
+// 转存的整体进度事件
+#[derive(Clone, Serialize)]
+struct TransferPayload {
+    id: i64, // 源文件夹 ID
+    files_total: usize,
+    files_done: usize,
+    files_failed: usize,
+    current: String,
+    status: String, // "transferring", "finished"
+}
+
+// 某个文件转存失败的原因
+#[derive(Clone, Serialize, Debug)]
+pub struct TransferFailure {
+    pub path: String,
+    pub error: String,
+}
+
+// 转存结束后返回给前端的汇总
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct TransferSummary {
+    pub folder_id: i64,        // 目标账号中新建的文件夹
+    pub reused: Vec<String>,   // 秒传成功
+    pub streamed: Vec<String>, // 经由下载再上传
+    pub failed: Vec<TransferFailure>,
+}
+
+// 在目标账号的 dest_folder_id 下重建源文件夹，文件逐个转存
+pub async fn run(
+    app: &AppHandle,
+    source: &ApiSession,
+    source_folder_id: i64,
+    folder_name: &str,
+    dest: &ApiSession,
+    dest_folder_id: i64,
+) -> Result<TransferSummary, String> {
+    info!("开始遍历源文件夹: {}", folder_name);
+    let entries = crate::walk_folder(source, source_folder_id).await?;
+    let mut payload = TransferPayload {
+        id: source_folder_id,
+        files_total: entries.iter().filter(|e| e.info.file_type != 1).count(),
+        files_done: 0,
+        files_failed: 0,
+        current: String::new(),
+        status: "transferring".to_string(),
+    };
+    info!("文件夹 {} 共 {} 个文件", folder_name, payload.files_total);
+
+    let folder_id = crate::create_remote_folder(dest, dest_folder_id, folder_name).await?;
+    let mut folders = RemoteFolders::created(folder_id);
+
+    let mut summary = TransferSummary {
+        folder_id,
+        ..TransferSummary::default()
+    };
+    for entry in entries {
+        let parent_rel = entry.path.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        let is_dir = entry.info.file_type == 1;
+        let target = if is_dir { &entry.path } else { parent_rel };
+        let result = match folders.get_or_create(dest, target).await {
+            Err(e) => Err(e),
+            // 空文件夹也要建出来
+            Ok(_) if is_dir => Ok(()),
+            Ok(parent_id) => {
+                payload.current = entry.path.clone();
+                app.emit("transfer-progress", payload.clone()).unwrap_or(());
+                match transfer_file(app, source, dest, parent_id, &entry).await {
+                    Ok(UploadOutcome::Reused) => {
+                        summary.reused.push(entry.path.clone());
+                        Ok(())
+                    }
+                    Ok(_) => {
+                        summary.streamed.push(entry.path.clone());
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        };
+
+        match result {
+            Ok(()) if !is_dir => payload.files_done += 1,
+            Ok(()) => {}
+            Err(e) => {
+                warn!("转存失败: {} - {}", entry.path, e);
+                if !is_dir {
+                    payload.files_failed += 1;
+                }
+                summary.failed.push(TransferFailure {
+                    path: entry.path,
+                    error: e,
+                });
+            }
+        }
+    }
+
+    info!(
+        "转存结束: {} (秒传 {}, 下载上传 {}, 失败 {})",
+        folder_name,
+        summary.reused.len(),
+        summary.streamed.len(),
+        payload.files_failed
+    );
+    payload.current = String::new();
+    payload.status = "finished".to_string();
+    app.emit("transfer-progress", payload).unwrap_or(());
+    Ok(summary)
+}
+
+// 先用源文件的 etag 和大小在目标账号秒传，不行时边下载边上传
+async fn transfer_file(
+    app: &AppHandle,
+    source: &ApiSession,
+    dest: &ApiSession,
+    parent_file_id: i64,
+    entry: &RemoteEntry,
+) -> Result<UploadOutcome, String> {
+    let info = &entry.info;
+    let etag = info
+        .etag
+        .as_deref()
+        .filter(|e| e.len() == 32)
+        .ok_or("缺少 Etag")?
+        .to_ascii_lowercase();
+    let id = format!("transfer:{}", info.file_id);
+    let target = StreamTarget {
+        id: &id,
+        parent_file_id,
+        file_name: &info.file_name,
+        etag: &etag,
+        size: info.size.max(0) as u64,
+    };
+    // 下载地址仍由当前应用的 resolver 解析和缓存
+    let main_state = app.state::<AppState>();
+    let resolver = &main_state.resolver;
+    upload::upload_stream(app, dest, &target, || open_source(source, resolver, info)).await
+}
+
+// 从源账号下载文件，返回响应体的数据流
+async fn open_source(
+    api: &ApiSession,
+    resolver: &DownloadUrlResolver,
+    info: &FileInfo,
+) -> Result<impl Stream<Item = reqwest::Result<impl AsRef<[u8]>>> + Unpin, String> {
+    info!("无法秒传，从源账号下载: {}", info.file_name);
+    let url = download::resolve_url(api, resolver, &DownloadJob::for_file(info)).await?;
+    let res = api
+        .client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("下载请求失败: {}", e))?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(format!("下载失败，服务器返回: {}", status));
+    }
+    Ok(Box::pin(res.bytes_stream()))
+}
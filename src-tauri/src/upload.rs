@@ -0,0 +1,1238 @@
+use futures_util::stream::FuturesUnordered;
+use futures_util::{Stream, StreamExt};
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_store::StoreExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::buffers::BufferPool;
+use crate::folder_upload::FolderUpload;
+use crate::hash_cache::{self, file_stamp};
+use crate::models::{
+    ApiResponse, ListPartsResponse, PresignedUrlResponse, UploadCompleteData, UploadRequestData,
+    UploadRequestResponse,
+};
+use crate::progress::{ProgressTracker, Snapshot};
+use crate::throttle::Throttle;
+use crate::{add_auth_headers, ApiSession, AppState, UploadProgressPayload};
+
+// 未完成的上传会话保存在这里，以本地文件路径为键
+const SESSION_STORE: &str = "uploads.json";
+
+// 分块大小: 按文件大小自动选择，至少 5MB (S3 的下限)
+// 大文件逐步加倍到 ADAPTIVE_MAX_BLOCK，让分块数不超过 TARGET_PARTS，减少每块的额外开销
+const MIN_BLOCK_SIZE: u64 = 5 * 1024 * 1024;
+const ADAPTIVE_MAX_BLOCK: u64 = 64 * 1024 * 1024;
+const TARGET_PARTS: u64 = 1000;
+// 手动指定时的上限，内存中同时有 PART_CONCURRENCY 块数据
+const MAX_BLOCK_SIZE: u64 = 1024 * 1024 * 1024;
+// S3 的分块数上限，超过时无论如何都要加大分块
+const MAX_PARTS: u64 = 10_000;
+
+// 一次申请这么多块的上传链接，分块较大时按字节数减少，免得链接在用到之前过期
+const PRESIGN_BATCH: u32 = 16;
+const PRESIGN_BATCH_BYTES: u64 = PRESIGN_BATCH as u64 * MIN_BLOCK_SIZE;
+// 同时上传的分块数，内存中最多同时有这么多块数据
+const PART_CONCURRENCY: usize = 4;
+// 单个分块失败后的重试次数，间隔从 1 秒开始翻倍
+const PART_RETRIES: u32 = 4;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+// 服务器异步合并文件时，确认完成的间隔和次数
+const COMPLETE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const COMPLETE_POLL_ATTEMPTS: u32 = 30;
+// 同名冲突等待前端回答的时间，超时后跳过
+const CONFLICT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// 一次分块上传的全部信息，每传完一块就写回磁盘，重启后可以继续
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadSession {
+    pub file_path: String,
+    pub file_name: String,
+    pub size: u64,
+    pub mtime: SystemTime, // 修改时间 (含纳秒)，和大小一起判断文件是否变过
+    pub etag: String,
+    pub parent_file_id: i64,
+    pub file_id: i64,
+    pub upload_id: String,
+    pub key: String,
+    pub bucket: String,
+    pub storage_node: String,
+    pub block_size: u64,
+    pub completed_parts: Vec<u32>,
+}
+
+impl UploadSession {
+    // 由上传请求的返回创建会话
+    fn new(
+        data: UploadRequestData,
+        file_path: &str,
+        file_name: &str,
+        etag: &str,
+        parent_file_id: i64,
+        size: u64,
+        mtime: SystemTime,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            file_path: file_path.to_string(),
+            file_name: file_name.to_string(),
+            size,
+            mtime,
+            etag: etag.to_string(),
+            parent_file_id,
+            file_id: data.file_id, // 最终完成时需要
+            upload_id: data.upload_id.ok_or("缺少 UploadId")?,
+            key: data.key.ok_or("缺少 Key")?,
+            bucket: data.bucket.ok_or("缺少 Bucket")?,
+            storage_node: data.storage_node.unwrap_or_default(),
+            block_size: block_size_for(size, None),
+            completed_parts: Vec::new(),
+        })
+    }
+
+    fn part_count(&self) -> u32 {
+        self.size.div_ceil(self.block_size) as u32
+    }
+
+    // 第 part 块 (从 1 开始) 的起始位置和长度
+    fn part_range(&self, part: u32) -> (u64, u64) {
+        let start = (part as u64 - 1) * self.block_size;
+        (start, self.block_size.min(self.size - start))
+    }
+
+    fn uploaded_bytes(&self) -> u64 {
+        self.completed_parts
+            .iter()
+            .map(|&p| self.part_range(p).1)
+            .sum()
+    }
+
+    // S3 相关接口共用的参数
+    fn s3_payload(&self) -> Value {
+        json!({
+            "bucket": self.bucket,
+            "key": self.key,
+            "uploadId": self.upload_id,
+            "storageNode": self.storage_node
+        })
+    }
+}
+
+// 按文件大小选择分块大小；requested 为手动指定的大小，仍受分块数上限约束
+pub fn block_size_for(size: u64, requested: Option<u64>) -> u64 {
+    let mut block = match requested {
+        Some(requested) => requested.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE),
+        None => {
+            let mut block = MIN_BLOCK_SIZE;
+            while size.div_ceil(block) > TARGET_PARTS && block < ADAPTIVE_MAX_BLOCK {
+                block = (block * 2).min(ADAPTIVE_MAX_BLOCK);
+            }
+            block
+        }
+    };
+    if size.div_ceil(block) > MAX_PARTS {
+        // 取整到 MB，方便看日志
+        block = size.div_ceil(MAX_PARTS).next_multiple_of(1024 * 1024);
+    }
+    block
+}
+
+// 一批申请多少块的上传链接
+fn presign_batch(block_size: u64) -> u32 {
+    (PRESIGN_BATCH_BYTES / block_size).clamp(PART_CONCURRENCY as u64, PRESIGN_BATCH as u64) as u32
+}
+
+// 网盘中已有同名文件时的处理方式
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    Ask,       // 发送 upload-conflict 事件，等前端回答
+    Overwrite, // duplicate = 1
+    #[default]
+    Rename, // duplicate = 2
+    Skip,
+}
+
+// 上传本地文件时的选项
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UploadOptions {
+    pub policy: ConflictPolicy,
+    pub part_size: Option<u64>, // 为空时按文件大小自动选择
+}
+
+// 一个文件上传的结果
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadOutcome {
+    Uploaded,
+    Reused, // 秒传
+    Skipped,
+}
+
+// 发起上传请求后的下一步
+enum Prepared {
+    Session(Box<UploadSession>),
+    Done(UploadOutcome),
+}
+
+#[derive(Clone, Serialize)]
+struct ConflictPayload {
+    id: String, // 回答时使用
+    file_path: String,
+    file_name: String,
+    parent_file_id: i64,
+}
+
+// 等待前端回答的同名冲突
+pub struct ConflictPrompts {
+    pending: Mutex<HashMap<String, oneshot::Sender<ConflictPolicy>>>,
+}
+
+impl ConflictPrompts {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 询问前端如何处理，超时没有回答时跳过
+    // 上传被取消时这个 future 会被丢弃，由 guard 清理等待中的记录
+    async fn ask(
+        &self,
+        app: &AppHandle,
+        file_path: &str,
+        file_name: &str,
+        parent_file_id: i64,
+    ) -> ConflictPolicy {
+        let id = Uuid::new_v4().simple().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id.clone(), tx);
+        let _guard = PendingConflict {
+            prompts: self,
+            id: id.clone(),
+        };
+
+        emit_progress(app, file_path, Snapshot::default(), "conflict");
+        app.emit(
+            "upload-conflict",
+            ConflictPayload {
+                id,
+                file_path: file_path.to_string(),
+                file_name: file_name.to_string(),
+                parent_file_id,
+            },
+        )
+        .unwrap_or(());
+        match tokio::time::timeout(CONFLICT_TIMEOUT, rx).await {
+            Ok(answer) => answer.unwrap_or(ConflictPolicy::Skip),
+            Err(_) => {
+                warn!("等待同名冲突的回答超时，跳过: {}", file_path);
+                ConflictPolicy::Skip
+            }
+        }
+    }
+
+    pub fn answer(&self, id: &str, policy: ConflictPolicy) -> Result<(), String> {
+        if policy == ConflictPolicy::Ask {
+            return Err("请选择覆盖、重命名或跳过".to_string());
+        }
+        let tx = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or("冲突不存在或已处理")?;
+        tx.send(policy).map_err(|_| "上传已结束".to_string())
+    }
+}
+
+// 等待回答期间持有，结束 (回答、超时或取消) 时移除对应的记录
+struct PendingConflict<'a> {
+    prompts: &'a ConflictPrompts,
+    id: String,
+}
+
+impl Drop for PendingConflict<'_> {
+    fn drop(&mut self) {
+        self.prompts.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+// 取消时返回的错误信息
+const CANCELLED: &str = "上传已取消";
+
+struct ActiveUpload {
+    token: CancellationToken,
+    s3_payload: Option<Value>, // 开始分块上传后才有，取消时用来清理服务器上的记录
+}
+
+// 进行中的上传，以进度事件中的 ID 为键，可以通过 cancel_upload 取消
+pub struct ActiveUploads {
+    uploads: Mutex<HashMap<String, ActiveUpload>>,
+}
+
+impl ActiveUploads {
+    pub fn new() -> Self {
+        Self {
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn register(&self, id: &str) -> Result<CancellationToken, String> {
+        let mut uploads = self.uploads.lock().unwrap();
+        if uploads.contains_key(id) {
+            return Err("该文件正在上传".to_string());
+        }
+        let token = CancellationToken::new();
+        uploads.insert(
+            id.to_string(),
+            ActiveUpload {
+                token: token.clone(),
+                s3_payload: None,
+            },
+        );
+        Ok(token)
+    }
+
+    fn attach(&self, id: &str, s3_payload: Value) {
+        if let Some(upload) = self.uploads.lock().unwrap().get_mut(id) {
+            upload.s3_payload = Some(s3_payload);
+        }
+    }
+
+    fn finish(&self, id: &str) -> Option<Value> {
+        self.uploads.lock().unwrap().remove(id)?.s3_payload
+    }
+
+    pub fn cancel(&self, id: &str) -> Result<(), String> {
+        let uploads = self.uploads.lock().unwrap();
+        let upload = uploads.get(id).ok_or("上传不存在或已结束")?;
+        upload.token.cancel();
+        Ok(())
+    }
+}
+
+// 运行一次上传，期间可以被取消；api 为上传目标账号的会话
+// 取消时中止正在进行的请求，并尝试让服务器丢弃未完成的分块上传
+pub async fn with_cancel<F>(
+    app: &AppHandle,
+    api: &ApiSession,
+    id: &str,
+    upload: F,
+) -> Result<UploadOutcome, String>
+where
+    F: Future<Output = Result<UploadOutcome, String>>,
+{
+    let main_state = app.state::<AppState>();
+    let token = main_state.uploads.register(id)?;
+    let result = tokio::select! {
+        biased;
+        result = upload => result,
+        _ = token.cancelled() => Err(CANCELLED.to_string()),
+    };
+    let s3_payload = main_state.uploads.finish(id);
+
+    match result {
+        Err(_) if token.is_cancelled() => {
+            info!("上传已取消: {}", id);
+            if let Some(s3_payload) = s3_payload {
+                abort(api, &s3_payload).await;
+            }
+            remove_session(app, id);
+            main_state.limits.upload.release(id);
+            emit_progress(app, id, Snapshot::default(), "cancelled");
+            Err(CANCELLED.to_string())
+        }
+        Err(e) => {
+            emit_error(app, id, &e);
+            Err(e)
+        }
+        ok => ok,
+    }
+}
+
+// 通知服务器放弃未完成的分块上传，失败时只记录日志
+async fn abort(api: &ApiSession, s3_payload: &Value) {
+    let url = "https://www.123pan.com/b/api/file/s3_abort_multipart_upload";
+    match post_api::<ApiResponse<Value>>(api, url, s3_payload).await {
+        Ok(res) if res.code == 0 => info!("已中止服务器上的分块上传"),
+        Ok(res) => warn!("中止分块上传失败: {}", res.message.unwrap_or_default()),
+        Err(e) => warn!("中止分块上传失败: {}", e),
+    }
+}
+
+// 上传请求已开启分块上传、但不会再用到时 (如没能秒传)，通知服务器放弃
+pub async fn abort_request(api: &ApiSession, data: &UploadRequestData) {
+    let Some(upload_id) = &data.upload_id else {
+        return;
+    };
+    let s3_payload = json!({
+        "bucket": data.bucket,
+        "key": data.key,
+        "uploadId": upload_id,
+        "storageNode": data.storage_node.clone().unwrap_or_default()
+    });
+    abort(api, &s3_payload).await;
+}
+
+pub fn emit_progress(app: &AppHandle, id: &str, snapshot: Snapshot, status: &str) {
+    app.emit(
+        "upload-progress",
+        UploadProgressPayload {
+            id: id.to_string(),
+            status: status.to_string(),
+            snapshot,
+            error: None,
+        },
+    )
+    .unwrap_or(());
+}
+
+// 以 error 状态结束并带上原因
+pub fn emit_error(app: &AppHandle, id: &str, error: &str) {
+    error!("上传失败: {} - {}", id, error);
+    app.emit(
+        "upload-progress",
+        UploadProgressPayload {
+            id: id.to_string(),
+            status: "error".to_string(),
+            snapshot: Snapshot::default(),
+            error: Some(error.to_string()),
+        },
+    )
+    .unwrap_or(());
+}
+
+pub fn load_session(app: &AppHandle, file_path: &str) -> Option<UploadSession> {
+    let store = app.store(SESSION_STORE).ok()?;
+    serde_json::from_value(store.get(file_path)?).ok()
+}
+
+fn save_session(app: &AppHandle, session: &UploadSession) -> Result<(), String> {
+    let store = app.store(SESSION_STORE).map_err(|e| e.to_string())?;
+    store.set(session.file_path.clone(), json!(session));
+    store.save().map_err(|e| e.to_string())
+}
+
+pub fn remove_session(app: &AppHandle, file_path: &str) {
+    if let Ok(store) = app.store(SESSION_STORE) {
+        store.delete(file_path);
+        store.save().unwrap_or(());
+    }
+}
+
+pub fn list_sessions(app: &AppHandle) -> Vec<UploadSession> {
+    let Ok(store) = app.store(SESSION_STORE) else {
+        return Vec::new();
+    };
+    store
+        .entries()
+        .into_iter()
+        .filter_map(|(_, value)| serde_json::from_value(value).ok())
+        .collect()
+}
+
+// 发起上传请求 (Upload Request)，duplicate: 0 询问, 1 覆盖, 2 重命名
+pub async fn request_upload(
+    api: &ApiSession,
+    parent_file_id: i64,
+    file_name: &str,
+    etag: &str,
+    size: u64,
+    duplicate: i32,
+) -> Result<UploadRequestResponse, String> {
+    let request_url = "https://www.123pan.com/b/api/file/upload_request";
+    let payload = json!({
+        "driveId": 0,
+        "etag": etag,
+        "fileName": file_name,
+        "parentFileId": parent_file_id,
+        "size": size,
+        "type": 0,
+        "duplicate": duplicate
+    });
+    post_api(api, request_url, &payload).await
+}
+
+// 带登录信息的 POST 请求，解析 JSON 响应
+pub async fn post_api<T: DeserializeOwned>(
+    api: &ApiSession,
+    url: &str,
+    payload: &Value,
+) -> Result<T, String> {
+    let token = api.token.lock().unwrap().clone();
+    let req = api.client.post(url).json(payload);
+    let req = add_auth_headers(req, &token, &api.login_uuid);
+    let res = req.send().await.map_err(|e| e.to_string())?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(format!("服务器返回错误状态: {}", status));
+    }
+    res.json().await.map_err(|e| e.to_string())
+}
+
+// 向服务器查询已经上传的分块
+async fn server_parts(api: &ApiSession, session: &UploadSession) -> Result<Vec<u32>, String> {
+    let url = "https://www.123pan.com/b/api/file/s3_list_upload_parts";
+    let res: ListPartsResponse = post_api(api, url, &session.s3_payload()).await?;
+    if res.code != 0 {
+        return Err(format!(
+            "查询已上传分块失败: {}",
+            res.message.unwrap_or_default()
+        ));
+    }
+
+    let parts = res.data.and_then(|d| d.parts).unwrap_or_default();
+    Ok(parts
+        .iter()
+        .filter_map(|p| match &p.part_number {
+            // 分块号有时是字符串
+            Value::String(s) => s.parse().ok(),
+            v => v.as_u64().map(|n| n as u32),
+        })
+        .filter(|&n| n >= 1 && n <= session.part_count())
+        .collect())
+}
+
+// 上传一个本地文件；失败时以 error 状态结束并带上原因，取消时以 cancelled 状态结束
+// group 不为空时同时更新所属文件夹的整体进度
+pub async fn run(
+    app: &AppHandle,
+    parent_file_id: i64,
+    file_path: &str,
+    options: UploadOptions,
+    group: Option<&FolderUpload>,
+) -> Result<UploadOutcome, String> {
+    let state = app.state::<AppState>();
+    let upload = upload(app, parent_file_id, file_path, options, group);
+    with_cancel(app, &state.api, file_path, upload).await
+}
+
+// 存在对应的未完成会话时从断点继续
+async fn upload(
+    app: &AppHandle,
+    parent_file_id: i64,
+    file_path: &str,
+    options: UploadOptions,
+    group: Option<&FolderUpload>,
+) -> Result<UploadOutcome, String> {
+    let state = app.state::<AppState>();
+    let (size, mtime) = file_stamp(file_path)?;
+
+    // 文件没变过时沿用上次的会话，以服务器记录的分块为准
+    let resumed = match load_session(app, file_path) {
+        Some(mut session)
+            if session.size == size
+                && session.mtime == mtime
+                && session.parent_file_id == parent_file_id =>
+        {
+            // 先登记会话，查询期间取消也能让服务器放弃这次分块上传
+            state.uploads.attach(file_path, session.s3_payload());
+            match server_parts(&state.api, &session).await {
+                Ok(parts) => {
+                    info!(
+                        "继续上传: {} (服务器已有 {}/{} 块)",
+                        session.file_name,
+                        parts.len(),
+                        session.part_count()
+                    );
+                    session.completed_parts = parts;
+                    Some(session)
+                }
+                Err(e) => {
+                    warn!("上传会话已失效，重新上传: {}", e);
+                    remove_session(app, file_path);
+                    None
+                }
+            }
+        }
+        Some(_) => {
+            info!("文件已变化，丢弃旧的上传会话: {}", file_path);
+            remove_session(app, file_path);
+            None
+        }
+        None => None,
+    };
+
+    let mut session = match resumed {
+        Some(session) => session,
+        None => match start(app, &state, parent_file_id, file_path, size, mtime, options).await? {
+            Prepared::Session(session) => *session,
+            Prepared::Done(outcome) => return Ok(outcome),
+        },
+    };
+
+    let mut tracker = ProgressTracker::new(size, session.uploaded_bytes());
+    let throttle = state.limits.upload.for_task(file_path);
+    upload_parts(
+        app,
+        &state.api,
+        &mut session,
+        &throttle,
+        &mut tracker,
+        group,
+    )
+    .await?;
+    complete(&state.api, &session).await?;
+
+    remove_session(app, file_path);
+    state.limits.upload.release(file_path);
+    info!("上传流程结束: {}", session.file_name);
+    emit_progress(app, file_path, tracker.finished(), "finished");
+    Ok(UploadOutcome::Uploaded)
+}
+
+// 计算 MD5 并发起上传请求；秒传成功或跳过时不需要再上传
+async fn start(
+    app: &AppHandle,
+    state: &AppState,
+    parent_file_id: i64,
+    file_path: &str,
+    size: u64,
+    mtime: SystemTime,
+    options: UploadOptions,
+) -> Result<Prepared, String> {
+    // 获取文件名
+    let file_name = std::path::Path::new(file_path)
+        .file_name()
+        .ok_or("无效的文件路径")?
+        .to_str()
+        .ok_or("文件名包含非UTF-8字符")?
+        .to_string();
+
+    // 1. 计算 MD5
+    emit_progress(app, file_path, Snapshot::default(), "hashing");
+    info!("正在计算文件 MD5: {}", file_name);
+    let (etag, _) = hash_cache::file_md5(app, file_path).await?;
+
+    // 2. 发起上传请求，第一次尝试默认 duplicate=0
+    let mut json_res =
+        request_upload(&state.api, parent_file_id, &file_name, &etag, size, 0).await?;
+
+    // 如果返回 5060 (文件已存在)，按冲突策略重试或跳过
+    if json_res.code == 5060 {
+        let policy = match options.policy {
+            ConflictPolicy::Ask => {
+                state
+                    .upload_conflicts
+                    .ask(app, file_path, &file_name, parent_file_id)
+                    .await
+            }
+            policy => policy,
+        };
+        let dup_policy = match policy {
+            ConflictPolicy::Overwrite => 1,
+            ConflictPolicy::Rename => 2,
+            ConflictPolicy::Skip | ConflictPolicy::Ask => {
+                info!("文件已存在，跳过: {}", file_name);
+                emit_progress(app, file_path, Snapshot::default(), "skipped");
+                return Ok(Prepared::Done(UploadOutcome::Skipped));
+            }
+        };
+        info!("文件已存在，按 {:?} 处理: {}", policy, file_name);
+        json_res = request_upload(
+            &state.api,
+            parent_file_id,
+            &file_name,
+            &etag,
+            size,
+            dup_policy,
+        )
+        .await?;
+    }
+
+    if json_res.code != 0 {
+        return Err(format!("上传请求拒绝: {}", json_res.message));
+    }
+
+    let data = json_res.data.ok_or("API 未返回数据")?;
+
+    // 3. 检查是否秒传
+    if data.reuse {
+        info!("秒传成功: {}", file_name);
+        let tracker = ProgressTracker::new(size, size);
+        emit_progress(app, file_path, tracker.finished(), "finished");
+        return Ok(Prepared::Done(UploadOutcome::Reused));
+    }
+
+    // 4. 准备分块上传 S3，先把会话写到磁盘
+    let mut session = UploadSession::new(
+        data,
+        file_path,
+        &file_name,
+        &etag,
+        parent_file_id,
+        size,
+        mtime,
+    )?;
+    // 服务器上已经开启了分块上传，马上登记，之后取消时才能通知服务器放弃
+    state.uploads.attach(file_path, session.s3_payload());
+    if let Some(part_size) = options.part_size {
+        session.block_size = block_size_for(size, Some(part_size));
+    }
+    info!(
+        "分块大小 {} MB，共 {} 块",
+        session.block_size / 1024 / 1024,
+        session.part_count()
+    );
+    save_session(app, &session)?;
+
+    // 初始化 S3 上传列表 (Python 源码逻辑)
+    server_parts(&state.api, &session).await?;
+    Ok(Prepared::Session(Box::new(session)))
+}
+
+// 待上传的一块，number 从 1 开始
+#[derive(Clone, Copy)]
+struct Part {
+    number: u32,
+    start: u64,
+    len: u64,
+}
+
+// 分块数据的来源
+#[derive(Clone, Copy)]
+enum PartData<'a> {
+    File(&'a str),    // 上传时才从本地文件读取
+    Memory(&'a [u8]), // 已经在内存中，如来自网络的数据
+}
+
+// 申请 [start, end) 范围内各分块的上传链接，键为分块号
+async fn presign(
+    api: &ApiSession,
+    s3_payload: &Value,
+    start: u32,
+    end: u32,
+) -> Result<HashMap<String, String>, String> {
+    let get_url_api = "https://www.123pan.com/b/api/file/s3_repare_upload_parts_batch";
+    let mut url_payload = s3_payload.clone();
+    url_payload["partNumberStart"] = json!(start);
+    url_payload["partNumberEnd"] = json!(end);
+    let json_url: PresignedUrlResponse = post_api(api, get_url_api, &url_payload).await?;
+
+    if json_url.code != 0 {
+        return Err(format!(
+            "获取上传链接失败: {}",
+            json_url.message.unwrap_or_default()
+        ));
+    }
+    Ok(json_url.data.map(|d| d.presigned_urls).unwrap_or_default())
+}
+
+// 从 S3 的 XML 错误响应中取出 Message
+fn s3_error_message(body: &str) -> &str {
+    body.split_once("<Message>")
+        .and_then(|(_, rest)| rest.split_once("</Message>"))
+        .map(|(message, _)| message)
+        .unwrap_or(body.trim())
+}
+
+// 把第 part 块读入缓冲区，一直读到满一块或文件结束；长度不对时不能上传
+async fn read_part(file_path: &str, part: Part, buffer: &mut Vec<u8>) -> Result<(), String> {
+    let mut file = tokio::fs::File::open(file_path)
+        .await
+        .map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(part.start))
+        .await
+        .map_err(|e| e.to_string())?;
+    file.take(part.len)
+        .read_to_end(buffer)
+        .await
+        .map_err(|e| e.to_string())?;
+    if buffer.len() as u64 != part.len {
+        return Err(format!(
+            "读取到 {} 字节，应为 {} 字节，文件可能在上传期间被修改",
+            buffer.len(),
+            part.len
+        ));
+    }
+    Ok(())
+}
+
+// 分块上传失败的原因: 网络或链接问题可以重试，本地文件出错时重试也没有用
+enum PartError {
+    Retryable(String),
+    Fatal(String),
+}
+
+impl From<String> for PartError {
+    fn from(e: String) -> Self {
+        PartError::Retryable(e)
+    }
+}
+
+impl From<&str> for PartError {
+    fn from(e: &str) -> Self {
+        PartError::Retryable(e.to_string())
+    }
+}
+
+// 上传一块；失败时重新申请链接，按指数退避重试
+async fn upload_part(
+    api: &ApiSession,
+    throttle: &Throttle,
+    s3_payload: &Value,
+    pool: &Arc<BufferPool>,
+    data: PartData<'_>,
+    part: Part,
+    mut url: Option<String>,
+) -> Result<Part, String> {
+    let mut attempt = 0;
+    loop {
+        let result = async {
+            let presigned_url = match url.take() {
+                Some(url) => url,
+                None => presign(api, s3_payload, part.number, part.number + 1)
+                    .await?
+                    .remove(&part.number.to_string())
+                    .ok_or("未找到对应分块的上传链接")?,
+            };
+            // 在这里才读取数据，同时在内存中的分块数不超过并发数
+            // 缓冲区随请求体一起释放，回到池中给下一块使用
+            let mut buffer = pool.take();
+            match data {
+                PartData::File(file_path) => read_part(file_path, part, &mut buffer)
+                    .await
+                    .map_err(PartError::Fatal)?,
+                PartData::Memory(bytes) => buffer.extend_from_slice(bytes),
+            }
+
+            // PUT 数据到 S3 (使用不带 Auth Header 的请求)
+            // 限速的请求体是流式的，需要手动写明长度，S3 不接受分块编码
+            let res = api
+                .client
+                .put(&presigned_url)
+                .header(reqwest::header::CONTENT_LENGTH, part.len)
+                .body(throttle.body(buffer))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            // S3 出错时返回 XML 格式的错误说明
+            let status = res.status();
+            if !status.is_success() {
+                let body = res.text().await.unwrap_or_default();
+                return Err(PartError::Retryable(format!(
+                    "S3 返回错误状态 {}: {}",
+                    status,
+                    s3_error_message(&body)
+                )));
+            }
+            Ok::<(), PartError>(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => return Ok(part),
+            Err(PartError::Retryable(e)) if attempt < PART_RETRIES => {
+                let delay = RETRY_BASE_DELAY * 2u32.pow(attempt);
+                attempt += 1;
+                warn!(
+                    "分块 {} 上传失败，{} 秒后重试 ({}/{}): {}",
+                    part.number,
+                    delay.as_secs(),
+                    attempt,
+                    PART_RETRIES,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(PartError::Retryable(e) | PartError::Fatal(e)) => {
+                return Err(format!("分块 {} 上传失败: {}", part.number, e))
+            }
+        }
+    }
+}
+
+// 上传服务器上还没有的分块: 按批申请链接，每批内并发上传
+// 申请一批分块的上传链接；批量申请失败时不中断，由各分块单独申请
+async fn presign_parts(
+    api: &ApiSession,
+    s3_payload: &Value,
+    batch: &[Part],
+) -> Vec<(Part, Option<String>)> {
+    let (first, last) = (batch[0].number, batch[batch.len() - 1].number);
+    let mut urls = presign(api, s3_payload, first, last + 1)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("批量获取上传链接失败: {}", e);
+            HashMap::new()
+        });
+    batch
+        .iter()
+        .map(|&part| (part, urls.remove(&part.number.to_string())))
+        .collect()
+}
+
+async fn upload_parts(
+    app: &AppHandle,
+    api: &ApiSession,
+    session: &mut UploadSession,
+    throttle: &Throttle,
+    tracker: &mut ProgressTracker,
+    group: Option<&FolderUpload>,
+) -> Result<(), String> {
+    let done: HashSet<u32> = session.completed_parts.iter().copied().collect();
+    let pending: Vec<Part> = (1..=session.part_count())
+        .filter(|n| !done.contains(n))
+        .map(|number| {
+            let (start, len) = session.part_range(number);
+            Part { number, start, len }
+        })
+        .collect();
+    let s3_payload = session.s3_payload();
+    let file_path = session.file_path.clone();
+    let pool = BufferPool::new(session.block_size as usize, PART_CONCURRENCY);
+    emit_progress(app, &file_path, tracker.snapshot(), "uploading");
+
+    // 一批内的分块号落在同一个申请范围里
+    let batch_size = presign_batch(session.block_size);
+    let mut batches =
+        pending.chunk_by(|a, b| (a.number - 1) / batch_size == (b.number - 1) / batch_size);
+    let mut ready = VecDeque::new();
+    // 上一批的链接到手后马上申请下一批，上传过程中不等待申请
+    let mut prefetch = batches
+        .next()
+        .map(|batch| Box::pin(presign_parts(api, &s3_payload, batch)));
+    let mut uploads = FuturesUnordered::new();
+    let mut errors = Vec::new();
+    loop {
+        while uploads.len() < PART_CONCURRENCY {
+            let Some((part, url)) = ready.pop_front() else {
+                break;
+            };
+            let data = PartData::File(&file_path);
+            uploads.push(upload_part(
+                api,
+                throttle,
+                &s3_payload,
+                &pool,
+                data,
+                part,
+                url,
+            ));
+        }
+        if uploads.is_empty() && prefetch.is_none() {
+            break;
+        }
+
+        tokio::select! {
+            parts = async { prefetch.as_mut().unwrap().await }, if prefetch.is_some() => {
+                ready.extend(parts);
+                prefetch = batches
+                    .next()
+                    .map(|batch| Box::pin(presign_parts(api, &s3_payload, batch)));
+            }
+            Some(result) = uploads.next() => match result {
+                Ok(part) => {
+                    // 记录进度，重启后只传剩下的分块
+                    session.completed_parts.push(part.number);
+                    save_session(app, session)?;
+                    if let Some(snapshot) = tracker.advance(part.len) {
+                        emit_progress(app, &file_path, snapshot, "uploading");
+                    }
+                    if let Some(group) = group {
+                        group.update(app, tracker.snapshot().bytes_done);
+                    }
+                }
+                Err(e) => {
+                    // 有分块彻底失败时整个上传都会失败，不再开始新的分块，等已开始的传完
+                    errors.push(e);
+                    ready.clear();
+                    prefetch = None;
+                }
+            },
+        }
+    }
+
+    // 只有全部分块都成功才继续完成上传
+    if !errors.is_empty() {
+        return Err(errors.join("; "));
+    }
+    Ok(())
+}
+
+// 所有分块都已上传，通知 S3 合并并完成上传
+async fn complete(api: &ApiSession, session: &UploadSession) -> Result<(), String> {
+    // 发送 S3 完成信号
+    let complete_s3_url = "https://www.123pan.com/b/api/file/s3_complete_multipart_upload";
+    let res: ApiResponse<Value> = post_api(api, complete_s3_url, &session.s3_payload())
+        .await
+        .map_err(|e| format!("S3 完成信号发送失败: {}", e))?;
+    if res.code != 0 {
+        return Err(format!(
+            "S3 合并分块失败: {}",
+            res.message.unwrap_or_default()
+        ));
+    }
+
+    // 发送业务完成信号；服务器可能在后台继续合并，这时隔一会再确认
+    let complete_api_url = "https://www.123pan.com/b/api/file/upload_complete";
+    let payload = json!({ "fileId": session.file_id });
+    for attempt in 1.. {
+        let res: ApiResponse<UploadCompleteData> =
+            post_api(api, complete_api_url, &payload).await?;
+        if res.code != 0 {
+            return Err(format!("完成上传失败: {}", res.message.unwrap_or_default()));
+        }
+        let pending = res
+            .data
+            .is_some_and(|d| d.is_async && d.completed != Some(true));
+        if !pending {
+            break;
+        }
+        if attempt >= COMPLETE_POLL_ATTEMPTS {
+            return Err("服务器仍在处理文件，请稍后在网盘中确认".to_string());
+        }
+        debug!("服务器正在合并文件，等待确认: {}", session.file_name);
+        tokio::time::sleep(COMPLETE_POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+// 流式上传的目标，数据来自网络等无法重新读取的来源
+pub struct StreamTarget<'a> {
+    pub id: &'a str, // 进度事件中的 ID
+    pub parent_file_id: i64,
+    pub file_name: &'a str,
+    pub etag: &'a str,
+    pub size: u64,
+}
+
+// 先用已知的 etag 尝试秒传，不行时才调用 open 取得数据流，攒够一块就上传一块
+// 会话不写入磁盘: 数据流无法从中间重新读取，失败后只能从头再来
+pub async fn upload_stream<F, Fut, S, B, E>(
+    app: &AppHandle,
+    api: &ApiSession,
+    target: &StreamTarget<'_>,
+    open: F,
+) -> Result<UploadOutcome, String>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<S, String>>,
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let upload = stream_upload(app, api, target, open);
+    with_cancel(app, api, target.id, upload).await
+}
+
+// upload_stream 中实际的上传过程，需要在上传前做其他准备的调用方自己套上 with_cancel
+pub async fn stream_upload<F, Fut, S, B, E>(
+    app: &AppHandle,
+    api: &ApiSession,
+    target: &StreamTarget<'_>,
+    open: F,
+) -> Result<UploadOutcome, String>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<S, String>>,
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let res = request_upload(
+        api,
+        target.parent_file_id,
+        target.file_name,
+        target.etag,
+        target.size,
+        2,
+    )
+    .await?;
+    if res.code != 0 {
+        return Err(format!("上传请求拒绝: {}", res.message));
+    }
+    let data = res.data.ok_or("API 未返回数据")?;
+    if data.reuse {
+        info!("秒传成功: {}", target.file_name);
+        let tracker = ProgressTracker::new(target.size, target.size);
+        emit_progress(app, target.id, tracker.finished(), "finished");
+        return Ok(UploadOutcome::Reused);
+    }
+
+    let session = UploadSession::new(
+        data,
+        target.id,
+        target.file_name,
+        target.etag,
+        target.parent_file_id,
+        target.size,
+        UNIX_EPOCH,
+    )?;
+    // 限速设置和取消都属于当前应用，和上传到哪个账号无关
+    let main_state = app.state::<AppState>();
+    main_state.uploads.attach(target.id, session.s3_payload());
+    server_parts(api, &session).await?;
+    let mut stream = open().await?;
+
+    let limits = &main_state.limits.upload;
+    let throttle = limits.for_task(target.id);
+    let s3_payload = session.s3_payload();
+    let mut tracker = ProgressTracker::new(target.size, 0);
+    emit_progress(app, target.id, tracker.snapshot(), "uploading");
+
+    // 数据先攒进缓冲区，按块的边界切开，满一块就上传
+    // 上传时另取一个缓冲区用于重试，内存中最多两块数据
+    let block_size = session.block_size as usize;
+    let pool = BufferPool::new(block_size, 2);
+    let mut buffer = pool.take();
+    let mut received = 0u64;
+    let mut number = 1;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("读取数据失败: {}", e))?;
+        let mut data = chunk.as_ref();
+        received += data.len() as u64;
+        if received > target.size {
+            return Err(format!("数据长度超过声明的 {} 字节", target.size));
+        }
+        while !data.is_empty() {
+            let n = (block_size - buffer.len()).min(data.len());
+            buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if buffer.len() == block_size {
+                let part = stream_part(
+                    api,
+                    &throttle,
+                    &s3_payload,
+                    &pool,
+                    &session,
+                    number,
+                    &buffer,
+                )
+                .await?;
+                buffer.clear();
+                number += 1;
+                if let Some(snapshot) = tracker.advance(part.len) {
+                    emit_progress(app, target.id, snapshot, "uploading");
+                }
+            }
+        }
+    }
+    // 最后不满一块的部分
+    if !buffer.is_empty() {
+        let part = stream_part(
+            api,
+            &throttle,
+            &s3_payload,
+            &pool,
+            &session,
+            number,
+            &buffer,
+        )
+        .await?;
+        tracker.advance(part.len);
+    }
+
+    if received != target.size {
+        return Err(format!(
+            "数据长度不符: 应为 {} 字节，实际收到 {} 字节",
+            target.size, received
+        ));
+    }
+    complete(api, &session).await?;
+
+    limits.release(target.id);
+    info!("上传流程结束: {}", target.file_name);
+    emit_progress(app, target.id, tracker.finished(), "finished");
+    Ok(UploadOutcome::Uploaded)
+}
+
+// 上传数据流中的第 number 块，除最后一块外长度都是一整块
+async fn stream_part(
+    api: &ApiSession,
+    throttle: &Throttle,
+    s3_payload: &Value,
+    pool: &Arc<BufferPool>,
+    session: &UploadSession,
+    number: u32,
+    data: &[u8],
+) -> Result<Part, String> {
+    let part = Part {
+        number,
+        start: session.part_range(number).0,
+        len: data.len() as u64,
+    };
+    let data = PartData::Memory(data);
+    upload_part(api, throttle, s3_payload, pool, data, part, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+    const GB: u64 = 1024 * MB;
+
+    #[test]
+    fn block_size_for_small_files() {
+        assert_eq!(block_size_for(0, None), MIN_BLOCK_SIZE);
+        assert_eq!(block_size_for(1, None), MIN_BLOCK_SIZE);
+        assert_eq!(block_size_for(5 * MB, None), MIN_BLOCK_SIZE);
+        assert_eq!(block_size_for(5 * MB + 1, None), MIN_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn block_size_for_target_parts_transitions() {
+        assert_eq!(block_size_for(TARGET_PARTS * 5 * MB, None), 5 * MB);
+        assert_eq!(block_size_for(TARGET_PARTS * 5 * MB + 1, None), 10 * MB);
+        assert_eq!(block_size_for(TARGET_PARTS * 10 * MB + 1, None), 20 * MB);
+        assert_eq!(block_size_for(TARGET_PARTS * 40 * MB, None), 40 * MB);
+        // 不会超过 ADAPTIVE_MAX_BLOCK
+        assert_eq!(
+            block_size_for(TARGET_PARTS * 40 * MB + 1, None),
+            ADAPTIVE_MAX_BLOCK
+        );
+        assert_eq!(block_size_for(100 * GB, None), ADAPTIVE_MAX_BLOCK);
+    }
+
+    #[test]
+    fn block_size_for_around_5_gb() {
+        for size in [5 * GB - 1, 5 * GB, 5 * GB + 1] {
+            assert_eq!(block_size_for(size, None), 10 * MB, "{}", size);
+        }
+    }
+
+    #[test]
+    fn block_size_for_max_parts_transition() {
+        let limit = MAX_PARTS * ADAPTIVE_MAX_BLOCK;
+        assert_eq!(block_size_for(limit, None), ADAPTIVE_MAX_BLOCK);
+        assert_eq!(block_size_for(limit + 1, None), ADAPTIVE_MAX_BLOCK + MB);
+        for size in [limit + 1, 2 * limit, 10 * 1024 * GB] {
+            let block = block_size_for(size, None);
+            assert!(size.div_ceil(block) <= MAX_PARTS, "{}", size);
+            assert_eq!(block % MB, 0);
+        }
+    }
+
+    #[test]
+    fn block_size_for_overrides() {
+        assert_eq!(block_size_for(GB, Some(MB)), MIN_BLOCK_SIZE);
+        assert_eq!(block_size_for(GB, Some(8 * MB)), 8 * MB);
+        assert_eq!(block_size_for(GB, Some(2 * GB)), MAX_BLOCK_SIZE);
+        // 指定的分块太小，分块数超过上限时仍会加大
+        assert_eq!(block_size_for(100 * GB, Some(5 * MB)), 11 * MB);
+    }
+
+    #[test]
+    fn presign_batch_by_block_size() {
+        assert_eq!(presign_batch(MIN_BLOCK_SIZE), PRESIGN_BATCH);
+        assert_eq!(presign_batch(MIN_BLOCK_SIZE - 1), PRESIGN_BATCH);
+        assert_eq!(presign_batch(10 * MB), 8);
+        assert_eq!(presign_batch(20 * MB), PART_CONCURRENCY as u32);
+        assert_eq!(presign_batch(ADAPTIVE_MAX_BLOCK), PART_CONCURRENCY as u32);
+        assert_eq!(presign_batch(MAX_BLOCK_SIZE), PART_CONCURRENCY as u32);
+    }
+}
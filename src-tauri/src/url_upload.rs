@@ -0,0 +1,184 @@
+use futures_util::StreamExt;
+use log::info;
+use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Response, Url};
+use tauri::{AppHandle, Manager};
+
+use crate::progress::Snapshot;
+use crate::upload::{self, StreamTarget, UploadOutcome};
+use crate::{ApiSession, AppState};
+
+// 没有指定文件名时取链接路径的最后一段 (解码 %20 等转义)
+fn name_from_url(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let name = percent_decode_str(segment).decode_utf8().ok()?;
+    (!name.is_empty()).then(|| name.into_owned())
+}
+
+async fn get(api: &ApiSession, url: &str) -> Result<Response, String> {
+    let res = api
+        .client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("请求链接失败: {}", e))?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(format!("链接返回错误状态: {}", status));
+    }
+    Ok(res)
+}
+
+// 服务器给出的文件大小，不支持 HEAD 或没有长度时为空
+async fn content_length(api: &ApiSession, url: &str) -> Option<u64> {
+    let res = api.client.head(url).send().await.ok()?;
+    if !res.status().is_success() {
+        return None;
+    }
+    res.headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+// 第一遍: 完整读取链接的内容，计算 MD5 和长度
+async fn hash_remote(api: &ApiSession, url: &str) -> Result<(String, u64), String> {
+    let mut stream = get(api, url).await?.bytes_stream();
+    let mut hasher = Md5::new();
+    let mut size = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("读取数据失败: {}", e))?;
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+// 确定文件名、MD5 和大小；给了 MD5 且服务器报告了长度时不需要先读一遍
+async fn probe(
+    app: &AppHandle,
+    api: &ApiSession,
+    url: &str,
+    file_name: Option<String>,
+    md5: Option<String>,
+) -> Result<(String, String, u64), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("无效的链接: {}", e))?;
+    let file_name = file_name
+        .filter(|name| !name.trim().is_empty())
+        .or_else(|| name_from_url(&parsed))
+        .ok_or("无法从链接确定文件名，请指定文件名")?;
+
+    let md5 = match md5.filter(|m| !m.trim().is_empty()) {
+        Some(m) if m.len() == 32 && m.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Some(m.to_ascii_lowercase())
+        }
+        Some(m) => return Err(format!("无效的 MD5: {}", m)),
+        None => None,
+    };
+    let size = match &md5 {
+        Some(_) => content_length(api, url).await,
+        None => None,
+    };
+
+    match (md5, size) {
+        (Some(md5), Some(size)) => Ok((file_name, md5, size)),
+        (supplied, _) => {
+            upload::emit_progress(app, url, Snapshot::default(), "hashing");
+            info!("正在计算链接内容的 MD5: {}", url);
+            let (md5, size) = hash_remote(api, url).await?;
+            if supplied.is_some_and(|m| m != md5) {
+                return Err(format!("MD5 不符: 链接内容的 MD5 为 {}", md5));
+            }
+            Ok((file_name, md5, size))
+        }
+    }
+}
+
+// 把链接指向的文件上传到 parent_file_id 下，数据直接从响应体写入分块，不落地
+// 从第一遍读取开始就登记为进行中的上传，计算 MD5 期间也可以取消
+pub async fn run(
+    app: &AppHandle,
+    parent_file_id: i64,
+    url: &str,
+    file_name: Option<String>,
+    md5: Option<String>,
+) -> Result<UploadOutcome, String> {
+    let state = app.state::<AppState>();
+    let api = &state.api;
+    let upload = async {
+        let (file_name, etag, size) = probe(app, api, url, file_name, md5).await?;
+        info!("开始从链接上传: {} ({} 字节)", file_name, size);
+
+        let target = StreamTarget {
+            id: url,
+            parent_file_id,
+            file_name: &file_name,
+            etag: &etag,
+            size,
+        };
+        // 第二遍请求只在无法秒传时才发出
+        let open = || async { Ok(Box::pin(get(api, url).await?.bytes_stream())) };
+        upload::stream_upload(app, api, &target, open).await
+    };
+    upload::with_cancel(app, api, url, upload).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, serve};
+
+    #[test]
+    fn name_from_url_decodes_last_segment() {
+        let name = |url: &str| name_from_url(&Url::parse(url).unwrap());
+        assert_eq!(
+            name("https://example.com/files/My%20File.zip?sign=1").as_deref(),
+            Some("My File.zip")
+        );
+        assert_eq!(
+            name("https://example.com/%E6%96%87%E4%BB%B6.txt").as_deref(),
+            Some("文件.txt")
+        );
+        assert_eq!(name("https://example.com/files/"), None);
+    }
+
+    #[tokio::test]
+    async fn hash_remote_reads_whole_body() {
+        let ok = response("200 OK", &[], "hello world");
+        let (base, server) = serve(vec![ok]).await;
+
+        let (md5, size) = hash_remote(&ApiSession::new(), &format!("{}/a.txt", base))
+            .await
+            .unwrap();
+        assert_eq!(md5, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert_eq!(size, 11);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_rejects_error_status() {
+        let missing = response("404 Not Found", &[], "not found");
+        let (base, server) = serve(vec![missing]).await;
+
+        let result = get(&ApiSession::new(), &format!("{}/a.txt", base)).await;
+        assert_eq!(
+            result.err().as_deref(),
+            Some("链接返回错误状态: 404 Not Found")
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn content_length_from_head() {
+        let head = "HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\nConnection: close\r\n\r\n";
+        let (base, server) = serve(vec![head.to_string()]).await;
+
+        let size = content_length(&ApiSession::new(), &format!("{}/a.bin", base)).await;
+        assert_eq!(size, Some(1048576));
+        assert!(server.await.unwrap()[0].starts_with("HEAD /a.bin"));
+    }
+}
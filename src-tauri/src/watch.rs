@@ -0,0 +1,325 @@
+use log::{info, warn};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_store::StoreExt;
+use tokio::sync::{mpsc, Semaphore};
+use walkdir::WalkDir;
+
+use crate::hash_cache::file_stamp;
+use crate::upload::{self, ConflictPolicy, UploadOptions, UploadOutcome};
+use crate::{AppState, RemoteFolders};
+
+// 监视的文件夹配置
+const WATCH_STORE: &str = "watch.json";
+const WATCH_KEY: &str = "folders";
+// 文件在这段时间内没有新的变化，才认为已经写完
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+// 检查待上传文件的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// 下载工具和编辑器在写入过程中使用的临时文件
+const TEMP_SUFFIXES: &[&str] = &[".tmp", ".part", ".crdownload", ".download", "~"];
+// 同时自动上传的文件数
+const UPLOAD_CONCURRENCY: usize = 3;
+
+// 一个本地文件夹和它对应的网盘文件夹，子目录在网盘中按需创建
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchFolder {
+    pub local_dir: String,
+    pub parent_file_id: i64,
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+}
+
+// 监视动态事件
+#[derive(Clone, Serialize)]
+struct WatchPayload {
+    local_dir: String, // 所属的监视文件夹
+    file_path: String,
+    status: String, // "detected", "uploaded", "reused", "skipped", "error"
+    error: Option<String>,
+}
+
+// 当前生效的文件监视，配置变化时整个换掉
+pub struct FolderWatcher {
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl FolderWatcher {
+    pub fn new() -> Self {
+        Self {
+            watcher: Mutex::new(None),
+        }
+    }
+}
+
+pub fn load(app: &AppHandle) -> Vec<WatchFolder> {
+    app.store(WATCH_STORE)
+        .ok()
+        .and_then(|store| store.get(WATCH_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+// 保存新的配置并立即生效，空列表表示停止监视
+pub fn configure(app: &AppHandle, folders: Vec<WatchFolder>) -> Result<(), String> {
+    for folder in &folders {
+        if !Path::new(&folder.local_dir).is_dir() {
+            return Err(format!("文件夹不存在: {}", folder.local_dir));
+        }
+    }
+    let store = app.store(WATCH_STORE).map_err(|e| e.to_string())?;
+    store.set(WATCH_KEY, json!(folders));
+    store.save().map_err(|e| e.to_string())?;
+    restart(app)
+}
+
+// 按保存的配置重新开始监视；某个文件夹无法监视时其余的照常进行
+pub fn restart(app: &AppHandle) -> Result<(), String> {
+    let folders = load(app);
+    let state = app.state::<AppState>();
+    let mut current = state.watcher.watcher.lock().unwrap();
+    // 旧的监视停掉后通道关闭，处理任务随之结束
+    *current = None;
+    if folders.is_empty() {
+        return Ok(());
+    }
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        tx.send(res).unwrap_or(());
+    })
+    .map_err(|e| format!("创建文件监视失败: {}", e))?;
+
+    let mut errors = Vec::new();
+    for folder in &folders {
+        match watcher.watch(Path::new(&folder.local_dir), RecursiveMode::Recursive) {
+            Ok(()) => info!("开始监视文件夹: {}", folder.local_dir),
+            Err(e) => {
+                warn!("无法监视文件夹: {} - {}", folder.local_dir, e);
+                errors.push(format!("{}: {}", folder.local_dir, e));
+            }
+        }
+    }
+    *current = Some(watcher);
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move { process(app, folders, rx).await });
+    if !errors.is_empty() {
+        return Err(format!("部分文件夹无法监视: {}", errors.join("; ")));
+    }
+    Ok(())
+}
+
+// 隐藏文件和写入中的临时文件不上传
+fn is_candidate(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+        return false;
+    };
+    !name.starts_with('.') && !TEMP_SUFFIXES.iter().any(|s| name.ends_with(s)) && path.is_file()
+}
+
+// 文件所属的监视文件夹 (取最深的一个) 和所在的相对目录 (用 / 分隔)
+fn find_folder<'a>(folders: &'a [WatchFolder], path: &Path) -> Option<(&'a WatchFolder, String)> {
+    folders
+        .iter()
+        .filter_map(|folder| {
+            let relative = path.strip_prefix(&folder.local_dir).ok()?;
+            let dir = relative
+                .parent()
+                .map(|p| {
+                    p.components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                })
+                .unwrap_or_default();
+            Some((folder, dir))
+        })
+        .max_by_key(|(folder, _)| folder.local_dir.len())
+}
+
+// 新建或移入的条目；移入的文件夹只有这一个事件，里面的文件要自己找出来
+fn is_added(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
+// 收集文件变化，等文件一段时间不再变化后交给后台任务上传
+// 上传在各自的任务中进行，一个文件上传很久或等待冲突回答时不影响其他文件
+async fn process(
+    app: AppHandle,
+    folders: Vec<WatchFolder>,
+    mut rx: mpsc::UnboundedReceiver<notify::Result<Event>>,
+) {
+    // 有变化的文件 -> 最后一次变化的时间
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    let uploader = Arc::new(Uploader::new(app));
+    let mut tick = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(Ok(event)) if event.kind.is_create() || event.kind.is_modify() => {
+                    let added = is_added(&event.kind);
+                    for path in event.paths {
+                        if added && path.is_dir() {
+                            let files = WalkDir::new(&path)
+                                .into_iter()
+                                .filter_map(|e| e.ok())
+                                .map(|e| e.into_path())
+                                .filter(|p| is_candidate(p));
+                            for file in files {
+                                pending.insert(file, Instant::now());
+                            }
+                        } else if is_candidate(&path) {
+                            pending.insert(path, Instant::now());
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => warn!("文件监视出错: {}", e),
+                None => break,
+            },
+            _ = tick.tick() => {
+                let ready: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, changed)| changed.elapsed() >= SETTLE_TIME)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in ready {
+                    let Some((folder, dir)) = find_folder(&folders, &path) else {
+                        pending.remove(&path);
+                        continue;
+                    };
+                    // 正在上传的文件又有变化时，等这次上传结束后再处理
+                    if !uploader.start(&path) {
+                        continue;
+                    }
+                    pending.remove(&path);
+                    let uploader = uploader.clone();
+                    let folder = folder.clone();
+                    tauri::async_runtime::spawn(async move {
+                        uploader.upload_changed(&folder, &dir, &path).await;
+                    });
+                }
+            }
+        }
+    }
+    info!("文件监视已停止");
+}
+
+// 后台上传任务共用的状态
+struct Uploader {
+    app: AppHandle,
+    slots: Semaphore,
+    // 正在上传的文件
+    active: Mutex<HashSet<PathBuf>>,
+    // 已上传文件的大小和修改时间，没变过的不再上传
+    uploaded: Mutex<HashMap<PathBuf, (u64, SystemTime)>>,
+    // 网盘根文件夹 ID -> 其下的文件夹
+    // 查找和创建期间一直持有，免得同时上传的文件重复创建同一个文件夹
+    remote_dirs: tokio::sync::Mutex<HashMap<i64, RemoteFolders>>,
+}
+
+impl Uploader {
+    fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            slots: Semaphore::new(UPLOAD_CONCURRENCY),
+            active: Mutex::new(HashSet::new()),
+            uploaded: Mutex::new(HashMap::new()),
+            remote_dirs: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 标记文件开始上传，已经在上传中时返回 false
+    fn start(&self, path: &Path) -> bool {
+        self.active.lock().unwrap().insert(path.to_path_buf())
+    }
+
+    async fn upload_changed(&self, folder: &WatchFolder, dir: &str, path: &Path) {
+        if let Ok(_slot) = self.slots.acquire().await {
+            self.upload(folder, dir, path).await;
+        }
+        self.active.lock().unwrap().remove(path);
+    }
+
+    async fn upload(&self, folder: &WatchFolder, dir: &str, path: &Path) {
+        let app = &self.app;
+        let file_path = path.to_string_lossy().to_string();
+        // 文件可能已被删除或改名
+        let Ok(stamp) = file_stamp(&file_path) else {
+            return;
+        };
+        if self.uploaded.lock().unwrap().get(path) == Some(&stamp) {
+            return;
+        }
+        info!("检测到文件变化: {}", file_path);
+        emit(app, folder, &file_path, "detected", None);
+
+        let options = UploadOptions {
+            policy: folder.conflict_policy,
+            part_size: None,
+        };
+        let parent_id = {
+            let state = app.state::<AppState>();
+            let mut remote_dirs = self.remote_dirs.lock().await;
+            let root_id = folder.parent_file_id;
+            remote_dirs
+                .entry(root_id)
+                .or_insert_with(|| RemoteFolders::existing(root_id))
+                .get_or_create(&state.api, dir)
+                .await
+        };
+        let result = match parent_id {
+            Ok(parent_id) => upload::run(app, parent_id, &file_path, options, None).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(outcome) => {
+                self.uploaded
+                    .lock()
+                    .unwrap()
+                    .insert(path.to_path_buf(), stamp);
+                let status = match outcome {
+                    UploadOutcome::Uploaded => "uploaded",
+                    UploadOutcome::Reused => "reused",
+                    UploadOutcome::Skipped => "skipped",
+                };
+                emit(app, folder, &file_path, status, None);
+            }
+            Err(e) => {
+                warn!("自动上传失败: {} - {}", file_path, e);
+                emit(app, folder, &file_path, "error", Some(e));
+            }
+        }
+    }
+}
+
+fn emit(
+    app: &AppHandle,
+    folder: &WatchFolder,
+    file_path: &str,
+    status: &str,
+    error: Option<String>,
+) {
+    app.emit(
+        "watch-activity",
+        WatchPayload {
+            local_dir: folder.local_dir.clone(),
+            file_path: file_path.to_string(),
+            status: status.to_string(),
+            error,
+        },
+    )
+    .unwrap_or(());
+}